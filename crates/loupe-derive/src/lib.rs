@@ -1,30 +1,311 @@
 use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
 use quote::{format_ident, quote, quote_spanned};
-use syn::{parse, Data, DataEnum, DataStruct, DeriveInput, Fields, Generics, Ident, Index};
-
-#[proc_macro_derive(MemoryUsage)]
+use syn::{
+    parse, parse_quote, spanned::Spanned, Attribute, Data, DataEnum, DataStruct, DataUnion,
+    DeriveInput, Error, Field, Fields, GenericArgument, Index, Lit, Meta, NestedMeta, Path,
+    PathArguments, Result, Type,
+};
+
+/// Derives `MemoryUsage` for a `struct`, an `enum` or a `union`.
+///
+/// The size of a value is its inline size plus what each of its fields
+/// owns outside of it. The way a field is measured can be changed with a
+/// `#[loupe(...)]` attribute on the field:
+///
+/// * `#[loupe(skip)]` only counts the inline size of the field. Its type
+///   doesn't have to implement `MemoryUsage`,
+/// * `#[loupe(with = "path::to::function")]` measures the field with a
+///   function having the same signature as `MemoryUsage::size_of_val`,
+///   i.e. `fn(&T, &mut dyn MemoryUsageTracker) -> usize`. Like it, the
+///   function returns the size of the field including its inline size,
+///   the generated code panics if it returns less,
+/// * `#[loupe(shallow)]` measures the field without following the
+///   references it contains, see `loupe::ShallowTracker`.
+///
+/// The name and the type of each measured field, and the variant of an
+/// `enum`, are given to the tracker, so that `loupe::report` and
+/// `loupe::histogram` can attribute the bytes to them. A field is not
+/// visited if the tracker stops the traversal, see
+/// `MemoryUsageTracker::should_stop`.
+///
+/// The active field of a `union` is unknown, so all its fields must be
+/// plain data, i.e. primitive types or arrays of them, and the union is
+/// counted by its inline size. Otherwise, the union must be measured by a
+/// function knowing the active field, given by a
+/// `#[loupe(with = "path::to::function")]` attribute on the union. In both
+/// cases, the fields of the union can't have `#[loupe(...)]` attributes.
+///
+/// When the `loupe` crate is re-exported under another path, the path can
+/// be set with a `#[loupe(crate = "path::to::loupe")]` attribute on the
+/// type.
+#[proc_macro_derive(MemoryUsage, attributes(loupe))]
 pub fn derive_memory_usage(input: TokenStream) -> TokenStream {
-    let derive_input: DeriveInput = parse(input).unwrap();
+    let derive_input = match parse::<DeriveInput>(input) {
+        Ok(derive_input) => derive_input,
+        Err(error) => return error.to_compile_error().into(),
+    };
+
+    match derive_memory_usage_for_input(&derive_input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+fn derive_memory_usage_for_input(derive_input: &DeriveInput) -> Result<TokenStream2> {
+    let container = ContainerAttributes::parse(&derive_input.attrs)?;
+
+    if let (Some(with), false) = (&container.with, matches!(derive_input.data, Data::Union(_))) {
+        return Err(Error::new_spanned(
+            with,
+            "`with` is only supported on unions, use it on the fields instead",
+        ));
+    }
 
     match derive_input.data {
         Data::Struct(ref struct_data) => {
-            derive_memory_usage_for_struct(&derive_input.ident, struct_data, &derive_input.generics)
+            derive_memory_usage_for_struct(derive_input, &container, struct_data)
         }
 
         Data::Enum(ref enum_data) => {
-            derive_memory_usage_for_enum(&derive_input.ident, enum_data, &derive_input.generics)
+            derive_memory_usage_for_enum(derive_input, &container, enum_data)
         }
 
-        Data::Union(_) => panic!("unions are not yet implemented"),
-        /*
-        // TODO: unions.
-        // We have no way of knowing which union member is active, so we should
-        // refuse to derive an impl except for unions where all members are
-        // primitive types or arrays of them.
         Data::Union(ref union_data) => {
-            derive_memory_usage_union(union_data)
+            derive_memory_usage_for_union(derive_input, &container, union_data)
+        }
+    }
+}
+
+/// Returns the meta items of all the `#[loupe(...)]` attributes.
+fn loupe_meta_items(attributes: &[Attribute]) -> Result<Vec<NestedMeta>> {
+    let mut items = vec![];
+
+    for attribute in attributes {
+        if !attribute.path.is_ident("loupe") {
+            continue;
+        }
+
+        match attribute.parse_meta()? {
+            Meta::List(list) => items.extend(list.nested),
+            meta => {
+                return Err(Error::new_spanned(
+                    meta,
+                    "expected an attribute of the form `#[loupe(...)]`",
+                ))
+            }
+        }
+    }
+
+    Ok(items)
+}
+
+/// Parses the string literal of a `name = "path"` meta item into a path.
+fn parse_path_literal(name: &str, literal: &Lit) -> Result<Path> {
+    match literal {
+        Lit::Str(string) => string.parse().map_err(|_| {
+            Error::new_spanned(
+                literal,
+                format!(
+                    "`{}` expects a path, e.g. `{} = \"path::to::item\"`",
+                    name, name
+                ),
+            )
+        }),
+        _ => Err(Error::new_spanned(
+            literal,
+            format!(
+                "`{}` expects a path in a string literal, e.g. `{} = \"path::to::item\"`",
+                name, name
+            ),
+        )),
+    }
+}
+
+/// Attributes of the type deriving `MemoryUsage`.
+struct ContainerAttributes {
+    /// Path to the `loupe` crate, given by `#[loupe(crate = "...")]`.
+    krate: Path,
+
+    /// Function measuring a union, given by `#[loupe(with = "...")]`.
+    with: Option<Path>,
+}
+
+impl ContainerAttributes {
+    fn parse(attributes: &[Attribute]) -> Result<Self> {
+        let mut krate = parse_quote!(::loupe);
+        let mut with = None;
+
+        for meta in loupe_meta_items(attributes)? {
+            match meta {
+                NestedMeta::Meta(Meta::NameValue(ref name_value))
+                    if name_value.path.is_ident("crate") =>
+                {
+                    krate = parse_path_literal("crate", &name_value.lit)?;
+                }
+
+                NestedMeta::Meta(Meta::NameValue(ref name_value))
+                    if name_value.path.is_ident("with") =>
+                {
+                    with = Some(parse_path_literal("with", &name_value.lit)?);
+                }
+
+                meta => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        "unknown `loupe` attribute, expected `crate = \"...\"` or `with = \"...\"`",
+                    ))
+                }
+            }
+        }
+
+        Ok(Self { krate, with })
+    }
+}
+
+/// How a field is measured, given by a `#[loupe(...)]` attribute.
+enum FieldAttribute {
+    /// No attribute, the field is measured with `MemoryUsage`.
+    Measure,
+
+    /// `#[loupe(skip)]`, only the inline size of the field is counted.
+    Skip,
+
+    /// `#[loupe(with = "...")]`, the field is measured by a function.
+    With(Path),
+
+    /// `#[loupe(shallow)]`, the field is measured without following
+    /// references.
+    Shallow,
+}
+
+impl FieldAttribute {
+    fn parse(field: &Field) -> Result<Self> {
+        let mut attribute = Self::Measure;
+
+        for meta in loupe_meta_items(&field.attrs)? {
+            let parsed = match meta {
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("skip") => Self::Skip,
+                NestedMeta::Meta(Meta::Path(ref path)) if path.is_ident("shallow") => Self::Shallow,
+                NestedMeta::Meta(Meta::NameValue(ref name_value))
+                    if name_value.path.is_ident("with") =>
+                {
+                    Self::With(parse_path_literal("with", &name_value.lit)?)
+                }
+
+                ref meta => {
+                    return Err(Error::new_spanned(
+                        meta,
+                        "unknown `loupe` attribute, expected `skip`, `with = \"...\"` or `shallow`",
+                    ))
+                }
+            };
+
+            if let Self::Measure = attribute {
+                attribute = parsed;
+            } else {
+                return Err(Error::new_spanned(
+                    meta,
+                    "a field can have only one of `skip`, `with` or `shallow`",
+                ));
+            }
+        }
+
+        Ok(attribute)
+    }
+
+    /// Parses the attributes of all the `fields`.
+    fn parse_all(fields: &Fields) -> Result<Vec<Self>> {
+        fields.iter().map(Self::parse).collect()
+    }
+
+    fn is_skip(&self) -> bool {
+        matches!(self, Self::Skip)
+    }
+}
+
+/// Generates the expression computing what `field` owns outside of its
+/// inline size. `value` is an expression of type `&FieldType`, and `name`
+/// is the name of the field given to the tracker.
+fn size_of_heap_of_field(
+    krate: &Path,
+    field: &Field,
+    name: &str,
+    attribute: &FieldAttribute,
+    value: TokenStream2,
+) -> TokenStream2 {
+    let span = field.span();
+    let ty = &field.ty;
+
+    // The `MemoryUsage` implementation is called through the field type, so
+    // that an unsatisfied trait bound points to the field.
+    let heap = match attribute {
+        FieldAttribute::Measure => quote_spanned! {
+            span => <#ty as #krate::MemoryUsage>::size_of_val(__loupe_value, visited) - ::std::mem::size_of_val(__loupe_value)
         },
-        */
+
+        FieldAttribute::Skip => quote! { 0 },
+
+        // The function returns the size of the field including its inline
+        // size, a smaller size is a bug in the function.
+        FieldAttribute::With(function) => quote_spanned! {
+            span => {
+                let __loupe_size = #function(__loupe_value, visited);
+                ::std::assert!(
+                    __loupe_size >= __loupe_inline,
+                    "`{}` returned {} bytes for the field `{}`, less than its inline size of {} bytes",
+                    ::std::stringify!(#function),
+                    __loupe_size,
+                    #name,
+                    __loupe_inline,
+                );
+
+                __loupe_size - __loupe_inline
+            }
+        },
+
+        FieldAttribute::Shallow => quote_spanned! {
+            span => <#ty as #krate::MemoryUsage>::size_of_val(__loupe_value, &mut #krate::ShallowTracker::new(visited))
+                - ::std::mem::size_of_val(__loupe_value)
+        },
+    };
+
+    if attribute.is_skip() {
+        return quote! { 0 };
+    }
+
+    // Tell the tracker which field is being measured, so that it can
+    // attribute the bytes to it, and let it stop the traversal before the
+    // field is visited. The block is parenthesized to be summed even in
+    // statement position.
+    //
+    // The locals are prefixed so that they don't shadow the fields bound
+    // by the caller, and `value` is bound first anyway.
+    quote! {
+        ({
+            let __loupe_value = #value;
+            let __loupe_type_name = ::std::any::type_name::<#ty>();
+            #krate::MemoryUsageTracker::enter_field(visited, #name, __loupe_type_name);
+            let __loupe_inline = ::std::mem::size_of_val(__loupe_value);
+            let __loupe_heap = if #krate::MemoryUsageTracker::should_stop(visited) {
+                #krate::MemoryUsageTracker::record_truncated(visited, __loupe_type_name);
+
+                0
+            } else {
+                let __loupe_heap = #heap;
+                #krate::MemoryUsageTracker::measured_value(
+                    visited,
+                    __loupe_type_name,
+                    __loupe_inline,
+                    __loupe_heap,
+                );
+
+                __loupe_heap
+            };
+            #krate::MemoryUsageTracker::exit_field(visited, __loupe_inline, __loupe_heap);
+
+            __loupe_heap
+        })
     }
 }
 
@@ -42,12 +323,14 @@ where
 }
 
 fn derive_memory_usage_for_struct(
-    struct_name: &Ident,
+    derive_input: &DeriveInput,
+    container: &ContainerAttributes,
     data: &DataStruct,
-    generics: &Generics,
-) -> TokenStream {
-    let lifetimes_and_generics = &generics.params;
-    let where_clause = &generics.where_clause;
+) -> Result<TokenStream2> {
+    let struct_name = &derive_input.ident;
+    let krate = &container.krate;
+    let (impl_generics, type_generics, where_clause) = derive_input.generics.split_for_impl();
+    let attributes = FieldAttribute::parse_all(&data.fields)?;
 
     let sum = join_fold(
         // Check all fields of the `struct`.
@@ -58,12 +341,16 @@ fn derive_memory_usage_for_struct(
             Fields::Named(ref fields) => fields
                 .named
                 .iter()
-                .map(|field| {
+                .zip(&attributes)
+                .map(|(field, attribute)| {
                     let ident = field.ident.as_ref().unwrap();
-                    let span = ident.span();
 
-                    quote_spanned!(
-                        span => MemoryUsage::size_of_val(&self.#ident, visited) - std::mem::size_of_val(&self.#ident)
+                    size_of_heap_of_field(
+                        krate,
+                        field,
+                        &ident.to_string(),
+                        attribute,
+                        quote! { &self.#ident },
                     )
                 })
                 .collect(),
@@ -79,11 +366,18 @@ fn derive_memory_usage_for_struct(
             Fields::Unnamed(ref fields) => fields
                 .unnamed
                 .iter()
+                .zip(&attributes)
                 .enumerate()
-                .map(|(nth, _field)| {
+                .map(|(nth, (field, attribute))| {
                     let ident = Index::from(nth);
 
-                    quote! { MemoryUsage::size_of_val(&self.#ident, visited) - std::mem::size_of_val(&self.#ident) }
+                    size_of_heap_of_field(
+                        krate,
+                        field,
+                        &nth.to_string(),
+                        attribute,
+                        quote! { &self.#ident },
+                    )
                 })
                 .collect(),
         }
@@ -93,31 +387,39 @@ fn derive_memory_usage_for_struct(
     );
 
     // Implement the `MemoryUsage` trait for `struct_name`.
-    (quote! {
-        #[allow(dead_code)]
-        impl < #lifetimes_and_generics > MemoryUsage for #struct_name < #lifetimes_and_generics >
+    Ok(quote! {
+        #[automatically_derived]
+        #[allow(dead_code, clippy::size_of_ref)]
+        impl #impl_generics #krate::MemoryUsage for #struct_name #type_generics
         #where_clause
         {
-            fn size_of_val(&self, visited: &mut MemoryUsageTracker) -> usize {
-                std::mem::size_of_val(self) + #sum
+            fn size_of_val(&self, visited: &mut dyn #krate::MemoryUsageTracker) -> usize {
+                ::std::mem::size_of_val(self) + #sum
             }
         }
     })
-    .into()
 }
 
 fn derive_memory_usage_for_enum(
-    enum_name: &Ident,
+    derive_input: &DeriveInput,
+    container: &ContainerAttributes,
     data: &DataEnum,
-    generics: &Generics,
-) -> TokenStream {
-    let lifetimes_and_generics = &generics.params;
-    let where_clause = &generics.where_clause;
+) -> Result<TokenStream2> {
+    let enum_name = &derive_input.ident;
+    let krate = &container.krate;
+    let (impl_generics, type_generics, where_clause) = derive_input.generics.split_for_impl();
+
+    let attributes = data
+        .variants
+        .iter()
+        .map(|variant| FieldAttribute::parse_all(&variant.fields))
+        .collect::<Result<Vec<_>>>()?;
 
     let match_arms = join_fold(
         data.variants
             .iter()
-            .map(|variant| {
+            .zip(&attributes)
+            .map(|(variant, attributes)| {
                 let ident = &variant.ident;
                 let span = ident.span();
 
@@ -133,135 +435,276 @@ fn derive_memory_usage_for_enum(
                 //           given by the `ident` variable
                 //
                 // Let's compute the `pattern` and `sum` parts.
-                let (pattern, sum) = match variant.fields {
-                    // Variant has the form:
-                    //
-                    //     V { x, y }
-                    //
-                    // We want to generate:
-                    //
-                    //     Self::V { x, y } => { /* memory usage of x + y */ }
-                    Fields::Named(ref fields) => {
-                        // Collect the identifiers.
-                        let identifiers = fields.named.iter().map(|field| {
-                            let ident = field.ident.as_ref().unwrap();
-                            let span = ident.span();
-
-                            quote_spanned!(span => #ident)
-                        });
-
-                        // Generate the `pattern` part.
-                        let pattern = {
-                            let pattern = join_fold(
-                                identifiers.clone(),
-                                |x, y| quote! { #x , #y },
-                                quote! {}
-                            );
-
-                            quote! { { #pattern } }
-                        };
-
-                        // Generate the `sum` part.
-                        let sum = {
-                            let sum = join_fold(
-                                identifiers.map(|ident| quote! {
-                                    MemoryUsage::size_of_val(#ident, visited) - std::mem::size_of_val(#ident)
-                                }),
-                                |x, y| quote! { #x + #y },
-                                quote! { 0 },
-                            );
-
-                            quote! { #sum }
-                        };
-
-                        (pattern, sum)
-                    }
-
-                    // Variant has the form:
-                    //
-                    //     V
-                    //
-                    // We want to generate:
-                    //
-                    //     Self::V => { 0 }
-                    Fields::Unit => {
-                        let pattern = quote! {};
-                        let sum = quote! { 0 };
-
-                        (pattern, sum)
-                    },
-
-                    // Variant has the form:
-                    //
-                    //     V(x, y)
-                    //
-                    // We want to generate:
-                    //
-                    //     Self::V(x, y) => { /* memory usage of x + y */ }
-                    Fields::Unnamed(ref fields) => {
-                        // Collect the identifiers. They are unnamed,
-                        // so let's use the `xi` convention where `i`
-                        // is the identifier index.
-                        let identifiers = fields
-                            .unnamed
-                            .iter()
-                            .enumerate()
-                            .map(|(nth, _field)| {
-                                let ident = format_ident!("x{}", Index::from(nth));
-
-                                quote! { #ident }
-                            });
-
-                        // Generate the `pattern` part.
-                        let pattern = {
-                            let pattern = join_fold(
-                                identifiers.clone(),
-                                |x, y| quote! { #x , #y },
-                                quote! {}
-                            );
-
-                            quote! { ( #pattern ) }
-                        };
-
-                        // Generate the `sum` part.
-                        let sum = {
-                            let sum = join_fold(
-                                identifiers.map(|ident| quote! {
-                                    MemoryUsage::size_of_val(#ident, visited) - std::mem::size_of_val(#ident)
-                                }),
-                                |x, y| quote! { #x + #y },
-                                quote! { 0 },
-                            );
-
-                            quote! { #sum }
-                        };
-
-                        (pattern, sum)
-                    }
-                };
+                let (pattern, sum) =
+                    match variant.fields {
+                        // Variant has the form:
+                        //
+                        //     V { x, y }
+                        //
+                        // We want to generate:
+                        //
+                        //     Self::V { x: __loupe_x, y: __loupe_y } => { /* memory usage of x + y */ }
+                        //
+                        // The fields are bound to prefixed names so that
+                        // they don't shadow `visited`. Skipped fields are
+                        // bound to `_`.
+                        Fields::Named(ref fields) => {
+                            // Generate the `pattern` part.
+                            let pattern = {
+                                let pattern = join_fold(
+                                    fields.named.iter().zip(attributes).map(
+                                        |(field, attribute)| {
+                                            let ident = field.ident.as_ref().unwrap();
+                                            let span = ident.span();
+
+                                            if attribute.is_skip() {
+                                                quote_spanned!(span => #ident: _)
+                                            } else {
+                                                let binding = format_ident!("__loupe_{}", ident);
+
+                                                quote_spanned!(span => #ident: #binding)
+                                            }
+                                        },
+                                    ),
+                                    |x, y| quote! { #x , #y },
+                                    quote! {},
+                                );
+
+                                quote! { { #pattern } }
+                            };
+
+                            // Generate the `sum` part.
+                            let sum = {
+                                let sum = join_fold(
+                                    fields.named.iter().zip(attributes).map(
+                                        |(field, attribute)| {
+                                            let ident = field.ident.as_ref().unwrap();
+                                            let binding = format_ident!("__loupe_{}", ident);
+
+                                            size_of_heap_of_field(
+                                                krate,
+                                                field,
+                                                &ident.to_string(),
+                                                attribute,
+                                                quote! { #binding },
+                                            )
+                                        },
+                                    ),
+                                    |x, y| quote! { #x + #y },
+                                    quote! { 0 },
+                                );
+
+                                quote! { #sum }
+                            };
+
+                            (pattern, sum)
+                        }
+
+                        // Variant has the form:
+                        //
+                        //     V
+                        //
+                        // We want to generate:
+                        //
+                        //     Self::V => { 0 }
+                        Fields::Unit => {
+                            let pattern = quote! {};
+                            let sum = quote! { 0 };
+
+                            (pattern, sum)
+                        }
+
+                        // Variant has the form:
+                        //
+                        //     V(x, y)
+                        //
+                        // We want to generate:
+                        //
+                        //     Self::V(x, y) => { /* memory usage of x + y */ }
+                        //
+                        // Skipped fields are bound to `_`.
+                        Fields::Unnamed(ref fields) => {
+                            // Collect the identifiers. They are unnamed,
+                            // so let's use the `xi` convention where `i`
+                            // is the identifier index.
+                            let identifiers = fields
+                                .unnamed
+                                .iter()
+                                .enumerate()
+                                .map(|(nth, _field)| format_ident!("x{}", Index::from(nth)));
+
+                            // Generate the `pattern` part.
+                            let pattern = {
+                                let pattern = join_fold(
+                                    identifiers.clone().zip(attributes).map(
+                                        |(ident, attribute)| {
+                                            if attribute.is_skip() {
+                                                quote! { _ }
+                                            } else {
+                                                quote! { #ident }
+                                            }
+                                        },
+                                    ),
+                                    |x, y| quote! { #x , #y },
+                                    quote! {},
+                                );
+
+                                quote! { ( #pattern ) }
+                            };
+
+                            // Generate the `sum` part.
+                            let sum = {
+                                let sum = join_fold(
+                                    identifiers
+                                        .enumerate()
+                                        .zip(fields.unnamed.iter().zip(attributes))
+                                        .map(|((nth, ident), (field, attribute))| {
+                                            size_of_heap_of_field(
+                                                krate,
+                                                field,
+                                                &nth.to_string(),
+                                                attribute,
+                                                quote! { #ident },
+                                            )
+                                        }),
+                                    |x, y| quote! { #x + #y },
+                                    quote! { 0 },
+                                );
+
+                                quote! { #sum }
+                            };
+
+                            (pattern, sum)
+                        }
+                    };
 
                 // At this step, `pattern` and `sum` are well
                 // defined. Let's generate the full arm for the
                 // `match` statement.
-                quote_spanned! { span => Self::#ident#pattern => #sum }
-            }
-        ),
+                let name = ident.to_string();
+
+                quote_spanned! { span => Self::#ident#pattern => {
+                    #krate::MemoryUsageTracker::variant(visited, #name);
+
+                    #sum
+                } }
+            }),
         |x, y| quote! { #x , #y },
         quote! {},
     );
 
     // Implement the `MemoryUsage` trait for `enum_name`.
-    (quote! {
-        #[allow(dead_code)]
-        impl < #lifetimes_and_generics > MemoryUsage for #enum_name < #lifetimes_and_generics >
+    Ok(quote! {
+        #[automatically_derived]
+        #[allow(dead_code, clippy::size_of_ref)]
+        impl #impl_generics #krate::MemoryUsage for #enum_name #type_generics
         #where_clause
         {
-            fn size_of_val(&self, visited: &mut MemoryUsageTracker) -> usize {
-                std::mem::size_of_val(self) + match self {
+            fn size_of_val(&self, visited: &mut dyn #krate::MemoryUsageTracker) -> usize {
+                ::std::mem::size_of_val(self) + match self {
                     #match_arms
                 }
             }
         }
     })
-    .into()
+}
+
+/// Returns whether `ty` is plain data, i.e. a primitive type, or an array,
+/// a tuple or a `ManuallyDrop` of plain data. Plain data owns nothing and
+/// holds no pointer.
+///
+/// The check is syntactic: a type alias of a primitive type isn't
+/// recognized.
+fn is_plain_data(ty: &Type) -> bool {
+    const PRIMITIVE_TYPES: &[&str] = &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize",
+    ];
+
+    match ty {
+        Type::Array(array) => is_plain_data(&array.elem),
+        Type::Group(group) => is_plain_data(&group.elem),
+        Type::Paren(paren) => is_plain_data(&paren.elem),
+        Type::Tuple(tuple) => tuple.elems.iter().all(is_plain_data),
+        Type::Path(path) if path.qself.is_none() => {
+            if let Some(ident) = path.path.get_ident() {
+                return PRIMITIVE_TYPES.iter().any(|primitive| ident == primitive);
+            }
+
+            match path.path.segments.last() {
+                Some(segment) if segment.ident == "ManuallyDrop" => match &segment.arguments {
+                    PathArguments::AngleBracketed(arguments) => {
+                        arguments.args.len() == 1
+                            && matches!(
+                                arguments.args.first(),
+                                Some(GenericArgument::Type(ty)) if is_plain_data(ty)
+                            )
+                    }
+                    _ => false,
+                },
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn derive_memory_usage_for_union(
+    derive_input: &DeriveInput,
+    container: &ContainerAttributes,
+    data: &DataUnion,
+) -> Result<TokenStream2> {
+    let union_name = &derive_input.ident;
+    let krate = &container.krate;
+    let (impl_generics, type_generics, where_clause) = derive_input.generics.split_for_impl();
+
+    // The fields are never measured one by one, an attribute on them would
+    // be ignored.
+    for field in &data.fields.named {
+        if let Some(attribute) = field
+            .attrs
+            .iter()
+            .find(|attribute| attribute.path.is_ident("loupe"))
+        {
+            return Err(Error::new_spanned(
+                attribute,
+                "`loupe` attributes are not supported on the fields of a union",
+            ));
+        }
+    }
+
+    // We have no way of knowing which union member is active. Either the
+    // user gives a function that knows it, or all the members must be
+    // plain data, in which case the union owns nothing.
+    let size = match container.with {
+        Some(ref function) => quote! { #function(self, visited) },
+
+        None => {
+            for field in &data.fields.named {
+                if !is_plain_data(&field.ty) {
+                    return Err(Error::new_spanned(
+                        &field.ty,
+                        "`MemoryUsage` can only be derived for unions whose fields are plain data, \
+                         like primitive types or arrays of them; use `#[loupe(with = \"...\")]` \
+                         on the union to measure it with a function that knows which field is active",
+                    ));
+                }
+            }
+
+            quote! { ::std::mem::size_of_val(self) }
+        }
+    };
+
+    // Implement the `MemoryUsage` trait for `union_name`.
+    Ok(quote! {
+        #[automatically_derived]
+        #[allow(dead_code)]
+        impl #impl_generics #krate::MemoryUsage for #union_name #type_generics
+        #where_clause
+        {
+            fn size_of_val(&self, visited: &mut dyn #krate::MemoryUsageTracker) -> usize {
+                #size
+            }
+        }
+    })
 }
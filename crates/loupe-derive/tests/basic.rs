@@ -2,6 +2,7 @@ use loupe::{MemoryUsage, MemoryUsageTracker};
 use loupe_derive::MemoryUsage;
 
 use std::collections::BTreeSet;
+use std::mem;
 
 macro_rules! assert_size_of_val_eq {
     ($expected:expr, $value:expr) => {
@@ -87,17 +88,248 @@ fn test_enum() {
         Points(Vec<Point>),
     }
 
-    assert_size_of_val_eq!(32, Things::A);
-    assert_size_of_val_eq!(32, Things::B());
-    assert_size_of_val_eq!(32, Things::C(1));
-    assert_size_of_val_eq!(32, Things::D { x: 1 });
-    assert_size_of_val_eq!(32, Things::E(1, 2));
-    assert_size_of_val_eq!(32, Things::F { x: 1, y: 2 });
+    // The layout of `Things` depends on the compiler, e.g. it may store
+    // the discriminant in a niche of `Vec`.
+    let things_size = mem::size_of::<Things>();
+
+    assert_size_of_val_eq!(things_size, Things::A);
+    assert_size_of_val_eq!(things_size, Things::B());
+    assert_size_of_val_eq!(things_size, Things::C(1));
+    assert_size_of_val_eq!(things_size, Things::D { x: 1 });
+    assert_size_of_val_eq!(things_size, Things::E(1, 2));
+    assert_size_of_val_eq!(things_size, Things::F { x: 1, y: 2 });
 
     assert_size_of_val_eq!(8, Point { x: 1, y: 2 });
     assert_size_of_val_eq!(40, vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }]);
     assert_size_of_val_eq!(
-        48,
+        things_size + 16,
         Things::Points(vec![Point { x: 1, y: 2 }, Point { x: 3, y: 4 }])
     );
 }
+
+#[test]
+fn test_enum_field_names() {
+    // The names of the locals of the generated code.
+    #[derive(MemoryUsage)]
+    enum Names {
+        A {
+            heap: u32,
+            inline: Vec<u8>,
+            type_name: String,
+            value: Box<u8>,
+            visited: Vec<u16>,
+        },
+    }
+
+    let names = Names::A {
+        heap: 1,
+        inline: Vec::with_capacity(2),
+        type_name: String::with_capacity(3),
+        value: Box::new(4),
+        visited: Vec::with_capacity(5),
+    };
+
+    assert_size_of_val_eq!(mem::size_of::<Names>() + 2 + 3 + 1 + 10, names);
+}
+
+#[test]
+fn test_skip() {
+    struct NotMemoryUsage;
+
+    #[allow(dead_code)]
+    #[derive(MemoryUsage)]
+    struct Skip {
+        x: Vec<u8>,
+        #[loupe(skip)]
+        y: Vec<u8>,
+        #[loupe(skip)]
+        z: NotMemoryUsage,
+    }
+
+    #[allow(dead_code)]
+    #[derive(MemoryUsage)]
+    enum Things {
+        A(#[loupe(skip)] Vec<u8>, Vec<u8>),
+        B {
+            #[loupe(skip)]
+            x: NotMemoryUsage,
+            y: Vec<u8>,
+        },
+    }
+
+    assert_size_of_val_eq!(
+        mem::size_of::<Skip>() + 16,
+        Skip {
+            x: vec![1; 16],
+            y: vec![1; 8],
+            z: NotMemoryUsage,
+        }
+    );
+    assert_size_of_val_eq!(
+        mem::size_of::<Things>() + 16,
+        Things::A(vec![1; 8], vec![1; 16])
+    );
+    assert_size_of_val_eq!(
+        mem::size_of::<Things>() + 16,
+        Things::B {
+            x: NotMemoryUsage,
+            y: vec![1; 16]
+        }
+    );
+}
+
+#[test]
+fn test_with() {
+    struct Opaque(Vec<u8>);
+
+    fn size_of_opaque(value: &Opaque, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(value) + value.0.size_of_val(tracker) - mem::size_of_val(&value.0)
+    }
+
+    #[derive(MemoryUsage)]
+    struct With {
+        #[loupe(with = "size_of_opaque")]
+        x: Opaque,
+    }
+
+    #[derive(MemoryUsage)]
+    enum Things {
+        A(#[loupe(with = "size_of_opaque")] Opaque),
+    }
+
+    assert_size_of_val_eq!(
+        mem::size_of::<With>() + 16,
+        With {
+            x: Opaque(vec![1; 16])
+        }
+    );
+    assert_size_of_val_eq!(
+        mem::size_of::<Things>() + 16,
+        Things::A(Opaque(vec![1; 16]))
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "`size_of_heap` returned 16 bytes for the field `x`, less than its inline size of 24 bytes"
+)]
+fn test_with_heap_only() {
+    // The function must return the inline size of the field as well.
+    fn size_of_heap(value: &Vec<u8>, _: &mut dyn MemoryUsageTracker) -> usize {
+        value.capacity()
+    }
+
+    #[derive(MemoryUsage)]
+    struct With {
+        #[loupe(with = "size_of_heap")]
+        x: Vec<u8>,
+    }
+
+    MemoryUsage::size_of_val(&With { x: vec![1; 16] }, &mut BTreeSet::new());
+}
+
+#[test]
+fn test_shallow() {
+    #[derive(MemoryUsage)]
+    struct Shallow<'a> {
+        #[loupe(shallow)]
+        x: &'a Vec<u8>,
+        #[loupe(shallow)]
+        y: Vec<&'a Vec<u8>>,
+        #[loupe(shallow)]
+        z: Box<[u8; 8]>,
+    }
+
+    let referenced = vec![1; 16];
+
+    assert_size_of_val_eq!(
+        // `x` and the element of `y` are references, their pointee is
+        // not counted. The pointee of `z` is owned.
+        mem::size_of::<Shallow>() + mem::size_of::<&Vec<u8>>() + 8,
+        Shallow {
+            x: &referenced,
+            y: vec![&referenced],
+            z: Box::new([1; 8]),
+        }
+    );
+}
+
+mod reexport {
+    pub use loupe as renamed_loupe;
+}
+
+#[test]
+fn test_crate_path() {
+    #[derive(MemoryUsage)]
+    #[loupe(crate = "crate::reexport::renamed_loupe")]
+    struct Renamed {
+        x: Vec<u8>,
+    }
+
+    assert_size_of_val_eq!(mem::size_of::<Renamed>() + 16, Renamed { x: vec![1; 16] });
+}
+
+#[test]
+fn test_generic_bounds() {
+    #[derive(MemoryUsage)]
+    struct Generic<'a, T: MemoryUsage, const N: usize> {
+        x: &'a [T; N],
+    }
+
+    let array = [1u32; 4];
+
+    assert_size_of_val_eq!(mem::size_of::<&[u32; 4]>() + 16, Generic { x: &array });
+}
+
+#[test]
+fn test_union() {
+    #[allow(dead_code)]
+    #[derive(MemoryUsage)]
+    #[repr(C)]
+    union Union {
+        x: u32,
+        y: [u8; 6],
+        z: (u16, [f32; 2]),
+        w: mem::ManuallyDrop<[[u64; 2]; 2]>,
+    }
+
+    assert_size_of_val_eq!(mem::size_of::<Union>(), Union { x: 1 });
+}
+
+#[test]
+fn test_union_with() {
+    #[repr(C)]
+    struct Tagged {
+        is_vec: bool,
+        value: Value,
+    }
+
+    #[repr(C)]
+    #[derive(MemoryUsage)]
+    #[loupe(with = "size_of_value")]
+    union Value {
+        integer: u64,
+        vec: mem::ManuallyDrop<Vec<u8>>,
+    }
+
+    // The tag lives outside of the union, so the function measures a
+    // `Value` that is always a `Vec` in this test.
+    fn size_of_value(value: &Value, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        let vec = unsafe { &value.vec };
+
+        mem::size_of_val(value) + vec.size_of_val(tracker) - mem::size_of_val(vec)
+    }
+
+    let tagged = Tagged {
+        is_vec: true,
+        value: Value {
+            vec: mem::ManuallyDrop::new(vec![1; 16]),
+        },
+    };
+
+    assert!(tagged.is_vec);
+    assert_size_of_val_eq!(mem::size_of::<Value>() + 16, tagged.value);
+
+    let Tagged { value, .. } = tagged;
+    drop(mem::ManuallyDrop::into_inner(unsafe { value.vec }));
+}
@@ -0,0 +1,51 @@
+use loupe::{MeasureError, MemoryUsage, Tracker};
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+struct Config {
+    name: String,
+    limits: Limits,
+}
+
+#[derive(MemoryUsage)]
+struct Limits {
+    rules: Vec<String>,
+    max: Option<u64>,
+}
+
+fn config(rules: usize) -> Config {
+    Config {
+        name: String::from("config"),
+        limits: Limits {
+            rules: vec![String::from("rule"); rules],
+            max: None,
+        },
+    }
+}
+
+#[test]
+fn test_budget() {
+    let config = config(4);
+    let size = config.size_of_val(&mut Tracker::new());
+
+    assert_eq!(Tracker::new().measure_within(&config, size), Ok(size));
+}
+
+#[test]
+fn test_budget_exceeded_path() {
+    let error = Tracker::new()
+        .measure_within(&config(1_000), 1024)
+        .unwrap_err();
+
+    assert_eq!(
+        error,
+        MeasureError::BudgetExceeded {
+            path: String::from("limits.rules"),
+            budget: 1024,
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "the budget of 1024 bytes has been exceeded at limits.rules"
+    );
+}
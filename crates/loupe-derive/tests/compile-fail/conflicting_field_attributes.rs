@@ -0,0 +1,11 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+enum Things {
+    A {
+        #[loupe(skip, shallow)]
+        x: i32,
+    },
+}
+
+fn main() {}
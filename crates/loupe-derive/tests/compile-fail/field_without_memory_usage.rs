@@ -0,0 +1,11 @@
+use loupe_derive::MemoryUsage;
+
+struct NotMemoryUsage;
+
+#[derive(MemoryUsage)]
+struct Point {
+    x: i32,
+    y: NotMemoryUsage,
+}
+
+fn main() {}
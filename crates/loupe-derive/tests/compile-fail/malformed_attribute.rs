@@ -0,0 +1,10 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+struct Point {
+    #[loupe = "skip"]
+    x: i32,
+    y: i32,
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+union Union {
+    x: u32,
+    #[loupe(skip)]
+    y: *const u8,
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+use loupe::MemoryUsageTracker;
+use loupe_derive::MemoryUsage;
+
+fn size_of_union(value: &Union, _: &mut dyn MemoryUsageTracker) -> usize {
+    std::mem::size_of_val(value)
+}
+
+#[derive(MemoryUsage)]
+#[loupe(with = "size_of_union")]
+union Union {
+    x: u32,
+    #[loupe(skip)]
+    y: *const u8,
+}
+
+fn main() {}
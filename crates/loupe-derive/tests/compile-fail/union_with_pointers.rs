@@ -0,0 +1,10 @@
+use loupe_derive::MemoryUsage;
+use std::mem::ManuallyDrop;
+
+#[derive(MemoryUsage)]
+union Union {
+    x: u32,
+    y: ManuallyDrop<Vec<u8>>,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+struct Point(#[loupe(with = "not a path")] i32, i32);
+
+fn main() {}
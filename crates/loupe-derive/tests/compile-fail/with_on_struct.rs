@@ -0,0 +1,10 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+#[loupe(with = "size_of_point")]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+fn main() {}
@@ -0,0 +1,6 @@
+use loupe_derive::MemoryUsage;
+
+#[derive(MemoryUsage)]
+struct Point(#[loupe(with = 42)] i32, i32);
+
+fn main() {}
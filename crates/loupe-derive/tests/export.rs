@@ -0,0 +1,95 @@
+use loupe_derive::MemoryUsage;
+use std::collections::HashMap;
+use std::mem;
+
+#[derive(MemoryUsage)]
+struct Index {
+    names: Vec<Name>,
+    kind: Kind,
+}
+
+#[derive(MemoryUsage)]
+struct Name(String);
+
+#[derive(MemoryUsage)]
+enum Kind {
+    Sparse,
+}
+
+fn index() -> Index {
+    Index {
+        names: vec![Name(String::from("a")), Name(String::from("bc"))],
+        kind: Kind::Sparse,
+    }
+}
+
+#[test]
+fn test_json() {
+    let index = index();
+    let report = loupe::report(&index);
+
+    let vec = mem::size_of::<Vec<Name>>();
+    let names = 2 * mem::size_of::<Name>() + 3;
+    let kind = mem::size_of::<Kind>();
+
+    assert_eq!(
+        report.to_json(),
+        format!(
+            concat!(
+                r#"{{"version":1,"total_bytes":{total},"records":["#,
+                r#"{{"path":"","type_name":"export::Index","variant":null,"count":1,"inline_bytes":{inline},"heap_bytes":{names},"total_bytes":{total},"truncated":false}},"#,
+                r#"{{"path":"names","type_name":"alloc::vec::Vec<export::Name>","variant":null,"count":1,"inline_bytes":{vec},"heap_bytes":{names},"total_bytes":{names_total},"truncated":false}},"#,
+                r#"{{"path":"names.0","type_name":"alloc::string::String","variant":null,"count":2,"inline_bytes":{strings},"heap_bytes":3,"total_bytes":{strings_total},"truncated":false}},"#,
+                r#"{{"path":"kind","type_name":"export::Kind","variant":"Sparse","count":1,"inline_bytes":{kind},"heap_bytes":0,"total_bytes":{kind},"truncated":false}}"#,
+                r#"]}}"#,
+            ),
+            total = mem::size_of::<Index>() + names,
+            inline = mem::size_of::<Index>(),
+            names = names,
+            vec = vec,
+            names_total = vec + names,
+            strings = 2 * mem::size_of::<String>(),
+            strings_total = 2 * mem::size_of::<String>() + 3,
+            kind = kind,
+        )
+    );
+}
+
+#[test]
+fn test_csv() {
+    let report = loupe::report(&index());
+    let csv = report.to_csv();
+    let mut lines = csv.lines();
+
+    assert_eq!(
+        lines.next(),
+        Some("path,type_name,variant,count,inline_bytes,heap_bytes,total_bytes,truncated")
+    );
+    assert_eq!(
+        lines
+            .map(|line| line.split(',').next().unwrap())
+            .collect::<Vec<_>>(),
+        ["", "names", "names.0", "kind"]
+    );
+    assert!(csv.contains(",export::Kind,Sparse,1,"));
+}
+
+#[test]
+fn test_csv_quoting() {
+    #[derive(MemoryUsage)]
+    struct Map {
+        entries: HashMap<u8, u8>,
+    }
+
+    let report = loupe::report(&Map {
+        entries: HashMap::new(),
+    });
+    let csv = report.to_csv();
+
+    assert!(csv.contains("\nentries,\"std::collections::hash::map::HashMap<u8, u8>\",,1,"));
+}
+
+#[test]
+fn test_schema_version() {
+    assert_eq!(loupe::REPORT_SCHEMA_VERSION, 1);
+}
@@ -0,0 +1,205 @@
+use loupe::{DotOptions, EdgeKind, GraphEdge, MemoryUsage, Tracker};
+use loupe_derive::MemoryUsage;
+use std::any::type_name;
+use std::mem;
+use std::rc::Rc;
+
+#[derive(MemoryUsage)]
+struct Document {
+    pages: Vec<String>,
+    title: Rc<String>,
+    cache: Box<Cache>,
+}
+
+#[derive(MemoryUsage)]
+struct Cache {
+    title: Rc<String>,
+    hits: u64,
+}
+
+fn address_of<T: ?Sized>(value: &T) -> *const () {
+    value as *const T as *const ()
+}
+
+#[test]
+fn test_graph() {
+    let title = Rc::new(String::from("title"));
+    let document = Document {
+        pages: vec![String::from("a"), String::from("bc")],
+        title: title.clone(),
+        cache: Box::new(Cache { title, hits: 0 }),
+    };
+
+    let graph = loupe::graph(&document);
+    let node = |address| graph.node_at(address).unwrap();
+
+    let pages = node(address_of(document.pages.as_slice()));
+    let first_page = node(address_of(document.pages[0].as_str()));
+    let title = node(address_of(&*document.title));
+    let title_buffer = node(address_of(document.title.as_str()));
+    let cache = node(address_of(&*document.cache));
+
+    assert_eq!(graph.root().type_name, type_name::<Document>());
+    assert_eq!(graph.root().bytes, mem::size_of::<Document>());
+    assert_eq!(graph.nodes().len(), 7);
+
+    assert_eq!(graph.nodes()[pages].type_name, type_name::<Vec<String>>());
+    assert_eq!(graph.nodes()[pages].bytes, 2 * mem::size_of::<String>());
+    assert_eq!(graph.nodes()[title].type_name, type_name::<Rc<String>>());
+    assert_eq!(graph.nodes()[title_buffer].bytes, 5);
+
+    for edge in [
+        (0, pages, EdgeKind::Owned),
+        (pages, first_page, EdgeKind::Owned),
+        (0, title, EdgeKind::Shared),
+        (title, title_buffer, EdgeKind::Owned),
+        (0, cache, EdgeKind::Owned),
+        (cache, title, EdgeKind::Shared),
+    ]
+    .iter()
+    .copied()
+    {
+        let (from, to, kind) = edge;
+        assert!(
+            graph.edges().contains(&GraphEdge { from, to, kind }),
+            "{:?}",
+            edge
+        );
+    }
+}
+
+#[test]
+fn test_retained_sizes() {
+    let title = Rc::new(String::from("title"));
+    let document = Document {
+        pages: vec![String::from("a"), String::from("bc")],
+        title: title.clone(),
+        cache: Box::new(Cache { title, hits: 0 }),
+    };
+
+    let graph = loupe::graph(&document);
+    let retained = graph.retained_sizes();
+    let dominators = graph.immediate_dominators();
+    let node = |address| graph.node_at(address).unwrap();
+
+    let pages = node(address_of(document.pages.as_slice()));
+    let title = node(address_of(&*document.title));
+    let cache = node(address_of(&*document.cache));
+
+    // The root retains everything, the shared title being counted once.
+    assert_eq!(dominators[0], None);
+    assert_eq!(
+        retained[0],
+        graph.nodes().iter().map(|node| node.bytes).sum::<usize>()
+    );
+    assert_eq!(retained[0], document.size_of_val(&mut Tracker::new()));
+
+    assert_eq!(retained[pages], 2 * mem::size_of::<String>() + 3);
+
+    // The title is also kept alive by the cache, so dropping either of
+    // them doesn't free it.
+    assert_eq!(dominators[title], Some(0));
+    assert_eq!(retained[cache], mem::size_of::<Cache>());
+    assert_eq!(
+        retained[title],
+        2 * mem::size_of::<usize>() + mem::size_of::<String>() + 5
+    );
+}
+
+#[test]
+fn test_borrowed_values_are_not_retained() {
+    let names = vec![String::from("a"), String::from("bc")];
+    let value = (&names, 1u8);
+
+    let graph = loupe::graph(&value);
+    let retained = graph.retained_sizes();
+    let dominators = graph.immediate_dominators();
+    let names_node = graph.node_at(address_of(&names)).unwrap();
+
+    assert_eq!(
+        graph.nodes()[names_node].type_name,
+        type_name::<Vec<String>>()
+    );
+    assert_eq!(
+        graph.nodes()[names_node].bytes,
+        mem::size_of::<Vec<String>>()
+    );
+    assert!(graph.edges().contains(&GraphEdge {
+        from: 0,
+        to: names_node,
+        kind: EdgeKind::Borrowed,
+    }));
+
+    assert_eq!(retained[0], mem::size_of_val(&value));
+    assert_eq!(dominators[names_node], None);
+    assert_eq!(retained[names_node], names.size_of_val(&mut Tracker::new()));
+}
+
+#[test]
+fn test_dot() {
+    let title = Rc::new(String::from("title"));
+    let document = Document {
+        pages: vec![String::from("a")],
+        title: title.clone(),
+        cache: Box::new(Cache { title, hits: 0 }),
+    };
+
+    let graph = loupe::graph(&document);
+    let dot = graph.to_dot(&DotOptions::new());
+    let retained = graph.retained_sizes();
+
+    assert!(dot.starts_with("digraph loupe {\n"));
+    assert!(dot.ends_with("}\n"));
+    assert!(dot.contains(&format!(
+        "    n0 [label=\"{}\\n{} bytes, {} retained\"];\n",
+        type_name::<Document>(),
+        mem::size_of::<Document>(),
+        retained[0],
+    )));
+
+    for (index, edge) in graph.edges().iter().enumerate() {
+        let attributes = match edge.kind {
+            EdgeKind::Owned => "label=\"owned\"",
+            EdgeKind::Shared => "label=\"shared\", style=dashed",
+            EdgeKind::Borrowed => "label=\"borrowed\", style=dotted",
+        };
+
+        assert!(
+            dot.contains(&format!(
+                "    n{} -> n{} [{}];\n",
+                edge.from, edge.to, attributes
+            )),
+            "{}",
+            index
+        );
+    }
+
+    assert_eq!(dot.matches(" -> ").count(), graph.edges().len());
+    assert_eq!(
+        dot.matches("[label=\"").count() - graph.edges().len(),
+        graph.nodes().len()
+    );
+}
+
+#[test]
+fn test_dot_cutoffs() {
+    let names = vec![String::from("a"), String::from("a long name")];
+    let graph = loupe::graph(&(&names, 1u8));
+
+    // The root, the names, and their buffer.
+    let dot = graph.to_dot(&DotOptions::new().with_max_depth(2));
+    assert_eq!(dot.matches("bytes, ").count(), 3);
+    assert!(dot.contains("\\n2 more not shown\"];"));
+    assert!(dot.contains("n0 -> n1 [label=\"borrowed\", style=dotted];"));
+
+    // The root, the names, their buffer, and the long name.
+    let dot = graph.to_dot(&DotOptions::new().with_min_retained_bytes(2));
+    assert_eq!(dot.matches("bytes, ").count(), 4);
+    assert!(dot.contains("\\n1 more not shown\"];"));
+    assert!(dot.contains("\\n11 bytes, 11 retained\"];"));
+
+    // The root is always rendered.
+    let dot = graph.to_dot(&DotOptions::new().with_min_retained_bytes(usize::MAX));
+    assert_eq!(dot.matches("bytes, ").count(), 1);
+    assert!(!dot.contains(" -> "));
+}
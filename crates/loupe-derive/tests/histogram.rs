@@ -0,0 +1,78 @@
+use loupe_derive::MemoryUsage;
+use std::any::type_name;
+use std::mem;
+use std::rc::Rc;
+
+#[derive(MemoryUsage)]
+struct Registry {
+    users: Vec<User>,
+    admin: Rc<User>,
+}
+
+#[derive(MemoryUsage)]
+struct User {
+    name: String,
+    #[loupe(skip)]
+    _id: u64,
+}
+
+fn user(name: &str) -> User {
+    User {
+        name: String::from(name),
+        _id: 0,
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let admin = Rc::new(user("root"));
+    let registry = Registry {
+        users: vec![user("a"), user("bc")],
+        admin: admin.clone(),
+    };
+    let histogram = loupe::histogram(&registry);
+
+    let users = histogram.get(type_name::<User>()).unwrap();
+    assert_eq!(users.count, 3);
+    assert_eq!(users.inline_bytes, 3 * mem::size_of::<User>());
+    assert_eq!(users.heap_bytes, 1 + 2 + 4);
+
+    let strings = histogram.get(type_name::<String>()).unwrap();
+    assert_eq!(strings.count, 3);
+    assert_eq!(strings.inline_bytes, 3 * mem::size_of::<String>());
+    assert_eq!(strings.heap_bytes, 1 + 2 + 4);
+
+    let registries = histogram.get(type_name::<Registry>()).unwrap();
+    assert_eq!(registries.count, 1);
+    assert_eq!(
+        registries.total_bytes(),
+        loupe::report(&registry).total_bytes()
+    );
+
+    assert_eq!(histogram.get(type_name::<Vec<User>>()).unwrap().count, 1);
+    assert_eq!(histogram.get(type_name::<Rc<User>>()).unwrap().count, 1);
+    assert!(histogram.get(type_name::<u64>()).is_none());
+}
+
+#[test]
+fn test_histogram_order() {
+    let histogram = loupe::histogram(&vec![user("a"), user("bc")]);
+    let totals = histogram
+        .entries()
+        .iter()
+        .map(|entry| entry.total_bytes())
+        .collect::<Vec<_>>();
+
+    assert_eq!(histogram.entries()[0].type_name, type_name::<Vec<User>>());
+    assert!(totals.windows(2).all(|pair| pair[0] >= pair[1]));
+}
+
+#[test]
+fn test_histogram_shared_value() {
+    let shared = Rc::new(user("shared"));
+    let histogram = loupe::histogram(&vec![shared.clone(), shared]);
+
+    // The `User` is visited through two `Rc`s, but measured once.
+    assert_eq!(histogram.get(type_name::<Rc<User>>()).unwrap().count, 2);
+    assert_eq!(histogram.get(type_name::<User>()).unwrap().count, 1);
+}
@@ -0,0 +1,65 @@
+use loupe::{DotOptions, MemoryUsage, Tracker};
+use loupe_derive::MemoryUsage;
+use std::any::type_name;
+use std::mem;
+
+#[derive(MemoryUsage)]
+struct List {
+    value: u64,
+    next: Option<Box<List>>,
+}
+
+fn list(length: usize) -> List {
+    (1..length).fold(
+        List {
+            value: 0,
+            next: None,
+        },
+        |next, value| List {
+            value: value as u64,
+            next: Some(Box::new(next)),
+        },
+    )
+}
+
+#[test]
+fn test_max_depth() {
+    let list = list(100);
+    let mut tracker = Tracker::new().with_max_depth(10);
+    let size = list.size_of_val(&mut tracker);
+
+    // The root and the 10 boxes visited, plus the box of the 11th node
+    // which isn't visited.
+    assert_eq!(size, 12 * mem::size_of::<List>());
+    assert_eq!(tracker.truncated_values(), [type_name::<List>()]);
+    assert!(size < list.size_of_val(&mut Tracker::new()));
+}
+
+#[test]
+fn test_report_truncated() {
+    let list = list(3);
+    let report = Tracker::new().with_max_depth(1).report(&list);
+
+    assert!(report.is_truncated());
+
+    // The second box is visited, not the node in it.
+    let next = report.root().get(&["next", "next"]).unwrap();
+    assert!(next.is_truncated());
+    assert!(!report.root().is_truncated());
+    assert!(!report.root().get(&["next"]).unwrap().is_truncated());
+    assert!(report.to_string().contains(", truncated)\n"));
+
+    assert!(!loupe::report(&list).is_truncated());
+}
+
+#[test]
+fn test_graph_truncated() {
+    let list = list(3);
+    let graph = Tracker::new().with_max_depth(1).graph(&list);
+    let second = list.next.as_ref().unwrap().next.as_deref().unwrap();
+
+    let node = graph.node_at(second as *const List as *const ()).unwrap();
+    assert!(graph.nodes()[node].truncated);
+    assert!(!graph.root().truncated);
+    assert!(graph.to_dot(&DotOptions::new()).contains("\\ntruncated"));
+}
@@ -0,0 +1,213 @@
+use loupe::{MemoryUsage, Tracker};
+use loupe_derive::MemoryUsage;
+use std::mem;
+
+#[derive(MemoryUsage)]
+struct Service {
+    name: String,
+    cache: Cache,
+    #[loupe(skip)]
+    _id: u64,
+}
+
+#[derive(MemoryUsage)]
+struct Cache {
+    entries: Vec<Entry>,
+    state: State,
+}
+
+#[derive(MemoryUsage)]
+struct Entry(u32, Box<[u8]>);
+
+#[derive(MemoryUsage)]
+enum State {
+    Cold,
+    Warm { hits: Vec<u64> },
+}
+
+fn service() -> Service {
+    Service {
+        name: String::with_capacity(10),
+        cache: Cache {
+            entries: vec![Entry(1, vec![0; 8].into()), Entry(2, vec![0; 24].into())],
+            state: State::Warm {
+                hits: Vec::with_capacity(4),
+            },
+        },
+        _id: 0,
+    }
+}
+
+#[test]
+fn test_report_total() {
+    let service = service();
+    let report = loupe::report(&service);
+
+    assert_eq!(
+        report.total_bytes(),
+        service.size_of_val(&mut Tracker::new())
+    );
+    assert_eq!(report.root().inline_bytes(), mem::size_of::<Service>());
+    assert_eq!(report.root().type_name(), std::any::type_name::<Service>());
+}
+
+#[test]
+fn test_report_fields() {
+    let report = loupe::report(&service());
+    let root = report.root();
+
+    let names = root
+        .children()
+        .iter()
+        .map(|child| child.name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["name", "cache"]);
+
+    let name = root.get(&["name"]).unwrap();
+    assert_eq!(name.type_name(), "alloc::string::String");
+    assert_eq!(name.inline_bytes(), mem::size_of::<String>());
+    assert_eq!(name.heap_bytes(), 10);
+
+    let entries = root.get(&["cache", "entries"]).unwrap();
+    assert_eq!(entries.heap_bytes(), 2 * mem::size_of::<Entry>() + 8 + 24);
+
+    // The fields of the items of the `Vec` are merged by name.
+    let boxes = root.get(&["cache", "entries", "1"]).unwrap();
+    assert_eq!(boxes.count(), 2);
+    assert_eq!(boxes.inline_bytes(), 2 * mem::size_of::<Box<[u8]>>());
+    assert_eq!(boxes.heap_bytes(), 8 + 24);
+
+    let state = root.get(&["cache", "state"]).unwrap();
+    assert_eq!(state.variant(), Some("Warm"));
+    assert_eq!(
+        state.get(&["hits"]).unwrap().heap_bytes(),
+        4 * mem::size_of::<u64>()
+    );
+}
+
+#[test]
+fn test_report_unit_variant() {
+    let report = loupe::report(&State::Cold);
+
+    assert_eq!(report.root().variant(), Some("Cold"));
+    assert!(report.root().children().is_empty());
+    assert_eq!(report.total_bytes(), mem::size_of::<State>());
+}
+
+#[test]
+fn test_report_display() {
+    let report = loupe::report(&State::Warm {
+        hits: Vec::with_capacity(2),
+    });
+
+    assert_eq!(
+        report.to_string(),
+        format!(
+            "report::State::Warm = {total} bytes ({inline} inline, 16 heap)\n  \
+             hits: alloc::vec::Vec<u64> = {vec} bytes ({vec_inline} inline, 16 heap)\n",
+            total = mem::size_of::<State>() + 16,
+            inline = mem::size_of::<State>(),
+            vec = mem::size_of::<Vec<u64>>() + 16,
+            vec_inline = mem::size_of::<Vec<u64>>(),
+        )
+    );
+}
+
+#[test]
+fn test_report_folded() {
+    let report = loupe::report(&service());
+    let folded = report.to_folded();
+
+    let service = mem::size_of::<Service>();
+    let entries = mem::size_of::<Vec<Entry>>() + 2 * mem::size_of::<Entry>()
+        - 2 * mem::size_of::<u32>()
+        - 2 * mem::size_of::<Box<[u8]>>();
+
+    // `cache` and `state` only have padding, which can be zero.
+    let expected = [
+        (
+            "report::Service",
+            service - mem::size_of::<String>() - mem::size_of::<Cache>(),
+        ),
+        ("report::Service;name", mem::size_of::<String>() + 10),
+        (
+            "report::Service;cache",
+            mem::size_of::<Cache>() - mem::size_of::<Vec<Entry>>() - mem::size_of::<State>(),
+        ),
+        ("report::Service;cache;entries", entries),
+        ("report::Service;cache;entries;0", 2 * mem::size_of::<u32>()),
+        (
+            "report::Service;cache;entries;1",
+            2 * mem::size_of::<Box<[u8]>>() + 8 + 24,
+        ),
+        (
+            "report::Service;cache;state",
+            mem::size_of::<State>() - mem::size_of::<Vec<u64>>(),
+        ),
+        (
+            "report::Service;cache;state;hits",
+            mem::size_of::<Vec<u64>>() + 4 * 8,
+        ),
+    ];
+
+    assert_eq!(
+        folded.lines().collect::<Vec<_>>(),
+        expected
+            .iter()
+            .filter(|(_, bytes)| *bytes > 0)
+            .map(|(stack, bytes)| format!("{} {}", stack, bytes))
+            .collect::<Vec<_>>()
+    );
+
+    // The flame graph has the size of the value.
+    let total = folded
+        .lines()
+        .map(|line| line.rsplit(' ').next().unwrap().parse::<usize>().unwrap())
+        .sum::<usize>();
+    assert_eq!(total, report.total_bytes());
+}
+
+#[test]
+fn test_report_folded_frames() {
+    // `;` separates the frames.
+    assert_eq!(loupe::report(&[1u8; 4]).to_folded(), "[u8, 4] 4\n");
+}
+
+#[test]
+fn test_report_diff() {
+    let mut service = service();
+    let before = loupe::report(&service);
+
+    service.name.reserve_exact(100);
+    service.cache.entries.truncate(1);
+    service.cache.state = State::Cold;
+    let after = loupe::report(&service);
+
+    let diff = before.diff(&after);
+
+    // `truncate` keeps the capacity, only what the removed item owns is
+    // freed. `hits` is missing from the `Cold` variant.
+    let name = service.name.capacity() as isize - 10;
+    let entry = mem::size_of::<u32>() as isize;
+    let boxed = mem::size_of::<Box<[u8]>>() as isize + 24;
+    let hits = mem::size_of::<Vec<u64>>() as isize + 32;
+
+    assert_eq!(
+        diff.entries()
+            .iter()
+            .map(|entry| (entry.path.as_str(), entry.growth()))
+            .collect::<Vec<_>>(),
+        [
+            ("name", name),
+            ("", name - 24 - 32),
+            ("cache.entries.0", -entry),
+            ("cache.entries", -24),
+            ("cache.state", -32),
+            ("cache.entries.1", -boxed),
+            ("cache", -24 - 32),
+            ("cache.state.hits", -hits),
+        ]
+        .to_vec()
+    );
+    assert_eq!(diff.get("cache.state.hits").unwrap().after_bytes, 0);
+}
@@ -0,0 +1,70 @@
+use loupe::{MemoryUsage, MemoryUsageTracker, SharedPolicy, Tracker};
+use loupe_derive::MemoryUsage;
+use std::mem;
+use std::rc::Rc;
+
+#[derive(MemoryUsage)]
+struct Registry {
+    users: Vec<Rc<String>>,
+    admin: Rc<String>,
+}
+
+/// Counts the entered fields, everything else being forwarded.
+struct FieldCounter<'a> {
+    tracker: &'a mut Tracker,
+    fields: Vec<&'static str>,
+}
+
+impl MemoryUsageTracker for FieldCounter<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn enter_field(&mut self, name: &'static str, type_name: &'static str) {
+        self.fields.push(name);
+        self.tracker.enter_field(name, type_name);
+    }
+}
+
+#[test]
+fn test_wrapping_tracker() {
+    let admin = Rc::new(String::from("root"));
+    let registry = Registry {
+        users: vec![admin.clone(), Rc::new(String::from("user"))],
+        admin,
+    };
+
+    let mut tracker = Tracker::new().with_shared_policy(SharedPolicy::Unowned);
+    let mut counter = FieldCounter {
+        tracker: &mut tracker,
+        fields: Vec::new(),
+    };
+
+    // The shared policy is the one of the wrapped tracker.
+    assert_eq!(
+        registry.size_of_val(&mut counter),
+        mem::size_of::<Registry>() + 2 * mem::size_of::<Rc<String>>()
+    );
+    assert_eq!(counter.fields, ["users", "admin"]);
+
+    // So are the shared allocations and the limits.
+    let shared_allocation = 2 * mem::size_of::<usize>() + mem::size_of::<String>();
+    assert_eq!(tracker.shared_bytes(), 2 * shared_allocation + 8);
+
+    let mut tracker = Tracker::new().with_max_depth(0);
+    let mut counter = FieldCounter {
+        tracker: &mut tracker,
+        fields: Vec::new(),
+    };
+
+    registry.size_of_val(&mut counter);
+    assert!(tracker.is_truncated());
+}
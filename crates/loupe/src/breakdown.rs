@@ -0,0 +1,152 @@
+use crate::{MeasureError, MemoryUsage, MemoryUsageTracker, Tracker};
+use std::mem;
+
+/// Returns the [`Breakdown`] of the memory used by `value`.
+///
+/// It uses a default [`Tracker`], see [`Tracker::measure_breakdown`] to
+/// configure it.
+///
+/// ```rust
+/// use std::rc::Rc;
+///
+/// let shared = Rc::new([0u8; 64]);
+/// let cache = (vec![0u64; 16], shared.clone());
+///
+/// let breakdown = loupe::breakdown(&cache);
+///
+/// assert_eq!(breakdown.inline_bytes, std::mem::size_of_val(&cache));
+/// assert_eq!(breakdown.heap_bytes, 16 * 8);
+/// assert_eq!(breakdown.shared_bytes, 2 * std::mem::size_of::<usize>() + 64);
+/// ```
+pub fn breakdown<T>(value: &T) -> Breakdown
+where
+    T: MemoryUsage + ?Sized,
+{
+    Tracker::new()
+        .measure_breakdown(value)
+        .expect("the default tracker skips the values it cannot measure")
+}
+
+/// The memory used by a value, split by where it lives.
+///
+/// The sum of the three parts is the size returned by
+/// [`MemoryUsage::size_of_val`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Breakdown {
+    /// The inline size of the value, i.e. `std::mem::size_of_val`. The
+    /// value can live on the stack or in a heap allocation owned by
+    /// something else.
+    pub inline_bytes: usize,
+    /// The bytes owned by the value outside of its inline size, except
+    /// the ones counted in `shared_bytes`.
+    pub heap_bytes: usize,
+    /// The bytes reached through shared pointers (`Rc`, `Arc`), as charged
+    /// by the [`SharedPolicy`](crate::SharedPolicy), and through
+    /// references (`&T`, etc.), including everything they own.
+    pub shared_bytes: usize,
+}
+
+impl Breakdown {
+    /// Returns the size of the value in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.inline_bytes + self.heap_bytes + self.shared_bytes
+    }
+}
+
+/// A [`MemoryUsageTracker`] summing the bytes charged to the outermost
+/// shared pointers and references, forwarding everything to another
+/// tracker.
+///
+/// It splits the shared bytes of a [`Breakdown`] and the bytes that the
+/// sampling of the elements of a collection does not extrapolate.
+pub(crate) struct ReferencedBytesTracker<'a> {
+    tracker: &'a mut dyn MemoryUsageTracker,
+    /// The number of shared pointers and references being measured.
+    depth: usize,
+    bytes: usize,
+}
+
+impl<'a> ReferencedBytesTracker<'a> {
+    pub(crate) fn new(tracker: &'a mut dyn MemoryUsageTracker) -> Self {
+        Self {
+            tracker,
+            depth: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Returns the bytes charged to the outermost shared pointers and
+    /// references measured so far.
+    pub(crate) fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn exit_reference(&mut self, bytes: usize) {
+        self.depth -= 1;
+
+        // The bytes charged to nested references are already part of the
+        // bytes charged to the outermost one.
+        if self.depth == 0 {
+            self.bytes += bytes;
+        }
+    }
+}
+
+impl MemoryUsageTracker for ReferencedBytesTracker<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn shared_reference(&mut self, address: *const ()) {
+        self.depth += 1;
+        self.tracker.shared_reference(address);
+    }
+
+    fn exit_shared_reference(&mut self, bytes: usize) {
+        self.exit_reference(bytes);
+        self.tracker.exit_shared_reference(bytes);
+    }
+
+    fn borrowed_reference(&mut self, address: *const (), type_name: &'static str) {
+        self.depth += 1;
+        self.tracker.borrowed_reference(address, type_name);
+    }
+
+    fn exit_borrowed_reference(&mut self, bytes: usize) {
+        self.exit_reference(bytes);
+        self.tracker.exit_borrowed_reference(bytes);
+    }
+}
+
+impl Tracker {
+    /// Returns the [`Breakdown`] of the memory used by `value`, measured
+    /// with this tracker.
+    ///
+    /// With the [`LockPolicy::Fail`](crate::LockPolicy::Fail) policy, an
+    /// error is returned if a value could not be measured.
+    pub fn measure_breakdown<T>(&mut self, value: &T) -> Result<Breakdown, MeasureError>
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        self.check_measured(|tracker| {
+            let mut tracker = ReferencedBytesTracker::new(tracker);
+
+            let size = value.size_of_val(&mut tracker);
+            let inline_bytes = mem::size_of_val(value);
+
+            Breakdown {
+                inline_bytes,
+                heap_bytes: size - inline_bytes - tracker.bytes(),
+                shared_bytes: tracker.bytes(),
+            }
+        })
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{MeasureError, MemoryUsage, MemoryUsageTracker, Tracker};
+use std::mem;
+
+/// A [`MemoryUsageTracker`] stopping the traversal as soon as the bytes
+/// visited exceed a budget, forwarding everything to a [`Tracker`].
+struct BudgetTracker<'a> {
+    tracker: &'a mut Tracker,
+    budget: usize,
+    /// The bytes visited so far.
+    bytes: usize,
+    /// The names of the entered fields.
+    path: Vec<&'static str>,
+    /// The visited bytes when entering each reference being visited.
+    references: Vec<usize>,
+    /// The path at which the budget has been exceeded, if it has.
+    exceeded: Option<String>,
+}
+
+impl BudgetTracker<'_> {
+    fn add(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes);
+
+        if self.bytes > self.budget && self.exceeded.is_none() {
+            self.exceeded = Some(self.path.join("."));
+        }
+    }
+}
+
+impl MemoryUsageTracker for BudgetTracker<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn enter_field(&mut self, name: &'static str, type_name: &'static str) {
+        self.path.push(name);
+        self.tracker.enter_field(name, type_name);
+    }
+
+    fn exit_field(&mut self, inline_bytes: usize, heap_bytes: usize) {
+        self.path.pop();
+        self.tracker.exit_field(inline_bytes, heap_bytes);
+    }
+
+    fn heap_allocation(&mut self, address: *const (), type_name: &'static str, bytes: usize) {
+        self.add(bytes);
+        self.tracker.heap_allocation(address, type_name, bytes);
+    }
+
+    fn borrowed_reference(&mut self, address: *const (), type_name: &'static str) {
+        self.references.push(self.bytes);
+        self.tracker.borrowed_reference(address, type_name);
+    }
+
+    fn exit_borrowed_reference(&mut self, bytes: usize) {
+        // The allocations of the pointee have already been added, what is
+        // left is its inline size.
+        let visited = self.bytes - self.references.pop().unwrap_or(self.bytes);
+        self.add(bytes.saturating_sub(visited));
+
+        self.tracker.exit_borrowed_reference(bytes);
+    }
+
+    fn should_stop(&mut self) -> bool {
+        self.exceeded.is_some() || self.tracker.should_stop()
+    }
+}
+
+impl Tracker {
+    /// Returns the size of `value` in bytes, like [`Tracker::measure`], or
+    /// an error if it exceeds `budget` bytes.
+    ///
+    /// The traversal stops as soon as the heap allocations and the
+    /// pointees visited so far exceed the budget, so that a huge value is
+    /// rejected without being walked entirely. The error gives the path of
+    /// the field being visited at that point, see
+    /// [`MeasureError::BudgetExceeded`].
+    ///
+    /// ```rust
+    /// use loupe::{MeasureError, Tracker};
+    /// use loupe_derive::MemoryUsage;
+    ///
+    /// #[derive(MemoryUsage)]
+    /// struct Config {
+    ///     name: String,
+    ///     rules: Vec<String>,
+    /// }
+    ///
+    /// let config = Config {
+    ///     name: String::from("config"),
+    ///     rules: vec![String::from("rule"); 1_000],
+    /// };
+    ///
+    /// assert!(matches!(
+    ///     Tracker::new().measure_within(&config, 1024),
+    ///     Err(MeasureError::BudgetExceeded { path, .. }) if path == "rules",
+    /// ));
+    /// ```
+    pub fn measure_within<T>(&mut self, value: &T, budget: usize) -> Result<usize, MeasureError>
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        let mut exceeded = None;
+        let size = self.check_measured(|tracker| {
+            let mut tracker = BudgetTracker {
+                tracker,
+                budget,
+                bytes: 0,
+                path: Vec::new(),
+                references: Vec::new(),
+                exceeded: None,
+            };
+
+            tracker.add(mem::size_of_val(value));
+            let size = value.size_of_val(&mut tracker);
+            exceeded = tracker.exceeded;
+
+            size
+        })?;
+
+        // Some allocations aren't visited, like the table of an empty
+        // `HashMap`, the size catches them.
+        match exceeded {
+            Some(path) => Err(MeasureError::BudgetExceeded { path, budget }),
+            None if size > budget => Err(MeasureError::BudgetExceeded {
+                path: String::new(),
+                budget,
+            }),
+            None => Ok(size),
+        }
+    }
+}
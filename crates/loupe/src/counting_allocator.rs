@@ -0,0 +1,190 @@
+use crate::{MemoryUsage, Tracker};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local! {
+    /// Bytes allocated minus bytes deallocated by the current thread.
+    static LIVE_BYTES: Cell<isize> = const { Cell::new(0) };
+}
+
+/// Whether a [`CountingAllocator`] has allocated something, i.e. whether
+/// it is the global allocator.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Records that a [`CountingAllocator`] has allocated something, called
+/// by every allocating method.
+fn install() {
+    INSTALLED.store(true, Ordering::Relaxed);
+}
+
+fn count(bytes: isize) {
+    // The counter has no destructor, but it is still unavailable once the
+    // thread is being destroyed. Those allocations are not counted.
+    let _ = LIVE_BYTES.try_with(|live_bytes| live_bytes.set(live_bytes.get() + bytes));
+}
+
+/// A global allocator counting the bytes allocated by each thread, to
+/// check the sizes computed by [`MemoryUsage`] against the real
+/// allocations.
+///
+/// It is opt-in: it must be declared as the global allocator of the
+/// program or of the test binary. It forwards everything to the wrapped
+/// allocator, [`System`] by default.
+///
+/// ```rust
+/// use loupe::CountingAllocator;
+///
+/// #[global_allocator]
+/// static ALLOCATOR: CountingAllocator = CountingAllocator::system();
+///
+/// fn main() {
+///     let check = loupe::check_heap_size(|| vec![1u8; 42]);
+///
+///     assert_eq!(check.allocated_bytes, 42);
+///     assert!(check.is_exact());
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct CountingAllocator<A = System> {
+    allocator: A,
+}
+
+impl CountingAllocator<System> {
+    /// Wraps the system allocator.
+    pub const fn system() -> Self {
+        Self { allocator: System }
+    }
+}
+
+impl<A> CountingAllocator<A> {
+    /// Wraps `allocator`.
+    pub const fn new(allocator: A) -> Self {
+        Self { allocator }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for CountingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pointer = self.allocator.alloc(layout);
+
+        if !pointer.is_null() {
+            install();
+            count(layout.size() as isize);
+        }
+
+        pointer
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let pointer = self.allocator.alloc_zeroed(layout);
+
+        if !pointer.is_null() {
+            install();
+            count(layout.size() as isize);
+        }
+
+        pointer
+    }
+
+    unsafe fn dealloc(&self, pointer: *mut u8, layout: Layout) {
+        self.allocator.dealloc(pointer, layout);
+        count(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, pointer: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_pointer = self.allocator.realloc(pointer, layout, new_size);
+
+        if !new_pointer.is_null() {
+            install();
+            count(new_size as isize - layout.size() as isize);
+        }
+
+        new_pointer
+    }
+}
+
+/// Counts the bytes allocated by the current thread since the scope has
+/// started, minus the bytes it has deallocated.
+///
+/// It requires a [`CountingAllocator`] as the global allocator. Bytes
+/// allocated by a thread and deallocated by another one are only counted
+/// as allocated by the first thread, and as deallocated by the second one.
+#[derive(Debug)]
+pub struct AllocationScope {
+    start: isize,
+}
+
+impl AllocationScope {
+    /// Starts counting.
+    pub fn new() -> Self {
+        Self {
+            start: LIVE_BYTES.with(Cell::get),
+        }
+    }
+
+    /// Returns the bytes allocated minus the bytes deallocated by the
+    /// current thread since the scope has started. It is negative if more
+    /// bytes have been deallocated.
+    pub fn live_bytes(&self) -> isize {
+        LIVE_BYTES.with(Cell::get) - self.start
+    }
+}
+
+impl Default for AllocationScope {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The result of [`check_heap_size`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapSizeCheck {
+    /// The bytes allocated to build the value, and still alive.
+    pub allocated_bytes: usize,
+    /// The bytes measured by [`MemoryUsage::size_of_val`] outside of the
+    /// inline size of the value.
+    pub measured_bytes: usize,
+}
+
+impl HeapSizeCheck {
+    /// Returns whether the measured bytes are the allocated bytes.
+    pub fn is_exact(&self) -> bool {
+        self.allocated_bytes == self.measured_bytes
+    }
+}
+
+/// Builds a value with `build`, and compares the bytes it has allocated
+/// with what [`MemoryUsage::size_of_val`] measures outside of its inline
+/// size.
+///
+/// `build` must allocate on the current thread only, and the value must
+/// own everything it allocates: a value shared with something alive
+/// before `build`, like a clone of an `Rc`, is measured but not allocated.
+///
+/// # Panics
+///
+/// Panics if the global allocator is not a [`CountingAllocator`].
+pub fn check_heap_size<T, F>(build: F) -> HeapSizeCheck
+where
+    T: MemoryUsage,
+    F: FnOnce() -> T,
+{
+    let scope = AllocationScope::new();
+    let value = build();
+    let live_bytes = scope.live_bytes();
+
+    // Measuring allocates, so it happens once the allocations are counted.
+    let measured_bytes = value.size_of_val(&mut Tracker::new()) - mem::size_of_val(&value);
+
+    assert!(
+        INSTALLED.load(Ordering::Relaxed),
+        "`check_heap_size` requires a `CountingAllocator` as the global allocator"
+    );
+
+    HeapSizeCheck {
+        allocated_bytes: live_bytes.max(0) as usize,
+        measured_bytes,
+    }
+}
@@ -0,0 +1,119 @@
+use crate::Report;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A field whose size differs between two reports, see [`Report::diff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiffEntry {
+    /// The names of the fields from the root joined by `.`, the root
+    /// having an empty path.
+    pub path: String,
+    /// The name of the type of the field, in the last report that has it.
+    pub type_name: &'static str,
+    /// The size of the field in the first report, zero if it is missing.
+    pub before_bytes: usize,
+    /// The size of the field in the second report, zero if it is missing.
+    pub after_bytes: usize,
+}
+
+impl DiffEntry {
+    /// Returns how much the field has grown in bytes, negative if it has
+    /// shrunk.
+    pub fn growth(&self) -> isize {
+        self.after_bytes as isize - self.before_bytes as isize
+    }
+}
+
+/// The differences between two reports of the same value, see
+/// [`Report::diff`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportDiff {
+    entries: Vec<DiffEntry>,
+}
+
+impl ReportDiff {
+    /// Returns the fields whose size differs, sorted by growth: the field
+    /// that has grown the most first, the field that has shrunk the most
+    /// last.
+    pub fn entries(&self) -> &[DiffEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry of the field at `path`, see [`DiffEntry::path`].
+    pub fn get(&self, path: &str) -> Option<&DiffEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+}
+
+impl fmt::Display for ReportDiff {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                formatter,
+                "{:+} bytes ({} -> {}) {}: {}",
+                entry.growth(),
+                entry.before_bytes,
+                entry.after_bytes,
+                if entry.path.is_empty() {
+                    "<root>"
+                } else {
+                    &entry.path
+                },
+                entry.type_name,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Report {
+    /// Returns the fields whose size differs between this report and
+    /// `after`, a later report of the same value.
+    ///
+    /// Fields are matched by path. A field missing from a report, e.g. a
+    /// field of another variant of an `enum`, has a size of zero in it.
+    ///
+    /// ```rust
+    /// let mut cache = vec![String::from("a")];
+    /// let before = loupe::report(&cache);
+    ///
+    /// cache.push(String::from("bc"));
+    /// let after = loupe::report(&cache);
+    ///
+    /// let diff = before.diff(&after);
+    /// assert!(diff.get("").unwrap().growth() > 0);
+    /// ```
+    pub fn diff(&self, after: &Report) -> ReportDiff {
+        let mut entries = BTreeMap::<String, DiffEntry>::new();
+
+        for (report, is_after) in [(self, false), (after, true)].iter().copied() {
+            report.root().visit(&mut Vec::new(), &mut |path, node| {
+                let path = path.join(".");
+                let entry = entries.entry(path.clone()).or_insert(DiffEntry {
+                    path,
+                    type_name: node.type_name(),
+                    before_bytes: 0,
+                    after_bytes: 0,
+                });
+
+                if is_after {
+                    entry.type_name = node.type_name();
+                    entry.after_bytes = node.total_bytes();
+                } else {
+                    entry.before_bytes = node.total_bytes();
+                }
+            });
+        }
+
+        let mut entries = entries
+            .into_values()
+            .filter(|entry| entry.growth() != 0)
+            .collect::<Vec<_>>();
+
+        // The entries are sorted by path, which breaks the ties.
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.growth()));
+
+        ReportDiff { entries }
+    }
+}
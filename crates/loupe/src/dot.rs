@@ -0,0 +1,163 @@
+use crate::{EdgeKind, Graph};
+use std::collections::VecDeque;
+use std::io;
+
+/// What to render of a [`Graph`] as DOT, see [`Graph::write_dot`].
+///
+/// By default, every node is rendered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DotOptions {
+    max_depth: Option<usize>,
+    min_retained_bytes: usize,
+}
+
+impl DotOptions {
+    /// Creates options rendering every node.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Renders only the nodes at most `depth` edges away from the root.
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+
+        self
+    }
+
+    /// Renders only the nodes retaining at least `bytes` bytes, see
+    /// [`Graph::retained_sizes`]. The root is always rendered.
+    pub fn with_min_retained_bytes(mut self, bytes: usize) -> Self {
+        self.min_retained_bytes = bytes;
+
+        self
+    }
+}
+
+/// Returns `text` as the content of a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Graph {
+    /// Writes the graph in the DOT language of GraphViz, e.g. to render it
+    /// with `dot -Tsvg`.
+    ///
+    /// A node is labelled with its type name, its size and its retained
+    /// size, and whether it is truncated, see
+    /// [`GraphNode::truncated`](crate::GraphNode::truncated). An edge is
+    /// labelled `owned`, `shared` (dashed) or `borrowed` (dotted), see
+    /// [`EdgeKind`].
+    ///
+    /// The nodes cut off by `options` are not rendered, neither are the
+    /// nodes only reachable through them. A rendered node referring to
+    /// nodes that are not says how many.
+    pub fn write_dot<W: io::Write>(&self, mut writer: W, options: &DotOptions) -> io::Result<()> {
+        let retained = self.retained_sizes();
+        let nodes = self.nodes();
+
+        // The nodes each node refers to, so that the edges are scanned once.
+        let mut successors = vec![Vec::new(); nodes.len()];
+        for edge in self.edges() {
+            successors[edge.from].push(edge.to);
+        }
+
+        // Breadth first from the root, so that a node has its smallest
+        // depth.
+        let mut rendered = vec![false; nodes.len()];
+        let mut queue = VecDeque::new();
+        rendered[0] = true;
+        queue.push_back((0, 0));
+
+        while let Some((node, depth)) = queue.pop_front() {
+            if options
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+            {
+                continue;
+            }
+
+            for &to in &successors[node] {
+                if !rendered[to] && retained[to] >= options.min_retained_bytes {
+                    rendered[to] = true;
+                    queue.push_back((to, depth + 1));
+                }
+            }
+        }
+
+        writeln!(writer, "digraph loupe {{")?;
+        writeln!(writer, "    node [shape=box];")?;
+
+        for (index, node) in nodes.iter().enumerate() {
+            if !rendered[index] {
+                continue;
+            }
+
+            let mut hidden = successors[index]
+                .iter()
+                .copied()
+                .filter(|&to| !rendered[to])
+                .collect::<Vec<_>>();
+            hidden.sort_unstable();
+            hidden.dedup();
+
+            write!(
+                writer,
+                "    n{} [label=\"{}\\n{} bytes, {} retained",
+                index,
+                escape(node.type_name),
+                node.bytes,
+                retained[index],
+            )?;
+
+            if node.truncated {
+                write!(writer, "\\ntruncated")?;
+            }
+
+            if !hidden.is_empty() {
+                write!(writer, "\\n{} more not shown", hidden.len())?;
+            }
+
+            writeln!(writer, "\"];")?;
+        }
+
+        for edge in self.edges() {
+            if !rendered[edge.from] || !rendered[edge.to] {
+                continue;
+            }
+
+            let attributes = match edge.kind {
+                EdgeKind::Owned => "label=\"owned\"",
+                EdgeKind::Shared => "label=\"shared\", style=dashed",
+                EdgeKind::Borrowed => "label=\"borrowed\", style=dotted",
+            };
+
+            writeln!(
+                writer,
+                "    n{} -> n{} [{}];",
+                edge.from, edge.to, attributes
+            )?;
+        }
+
+        writeln!(writer, "}}")
+    }
+
+    /// Returns the graph in the DOT language, see [`Graph::write_dot`].
+    ///
+    /// ```rust
+    /// use loupe::DotOptions;
+    ///
+    /// let names = vec![String::from("a"), String::from("bc")];
+    /// let dot = loupe::graph(&names).to_dot(&DotOptions::new().with_max_depth(1));
+    ///
+    /// assert!(dot.starts_with("digraph loupe {"));
+    /// assert!(dot.contains("n0 -> n1 [label=\"owned\"];"));
+    /// assert!(!dot.contains("n1 -> n2"));
+    /// ```
+    pub fn to_dot(&self, options: &DotOptions) -> String {
+        let mut dot = Vec::new();
+        self.write_dot(&mut dot, options)
+            .expect("writing to a `Vec` never fails");
+
+        String::from_utf8(dot).expect("DOT is UTF-8")
+    }
+}
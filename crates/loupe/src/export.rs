@@ -0,0 +1,137 @@
+use crate::{Report, ReportNode};
+use serde::Serialize;
+use std::io::{self, Write};
+
+/// The version of the schema of the exported reports, see
+/// [`Report::write_json`] and [`Report::write_csv`].
+///
+/// It changes only when a field is removed or changes meaning. Fields can
+/// be added to the same version.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// A report, as exported to JSON.
+#[derive(Serialize)]
+struct JsonReport {
+    version: u32,
+    total_bytes: usize,
+    records: Vec<Record>,
+}
+
+/// A node of a report, as exported to JSON and CSV.
+#[derive(Serialize)]
+struct Record {
+    path: String,
+    type_name: &'static str,
+    variant: Option<&'static str>,
+    count: usize,
+    inline_bytes: usize,
+    heap_bytes: usize,
+    total_bytes: usize,
+    truncated: bool,
+}
+
+/// The columns of the CSV export, in the order of the fields of [`Record`].
+const CSV_HEADER: &str =
+    "path,type_name,variant,count,inline_bytes,heap_bytes,total_bytes,truncated";
+
+impl Record {
+    fn new(path: &[&str], node: &ReportNode) -> Self {
+        Self {
+            path: path.join("."),
+            type_name: node.type_name(),
+            variant: node.variant(),
+            count: node.count(),
+            inline_bytes: node.inline_bytes(),
+            heap_bytes: node.heap_bytes(),
+            total_bytes: node.total_bytes(),
+            truncated: node.is_truncated(),
+        }
+    }
+}
+
+/// Returns `field` as a CSV field, quoted if needed.
+fn csv_field(field: &str) -> String {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+impl Report {
+    /// Returns the nodes of the report, depth first.
+    fn records(&self) -> Vec<Record> {
+        let mut records = Vec::new();
+        self.root().visit(&mut Vec::new(), &mut |path, node| {
+            records.push(Record::new(path, node))
+        });
+
+        records
+    }
+
+    /// Writes the report as a JSON document.
+    ///
+    /// The document is an object with a `version`, see
+    /// [`REPORT_SCHEMA_VERSION`], the `total_bytes` of the report, and the
+    /// `records` of its nodes, depth first. A record has the `path` of the
+    /// node, i.e. the names of the fields from the root joined by `.`, the
+    /// root having an empty path, and its `type_name`, `variant` (or
+    /// `null`), `count`, `inline_bytes`, `heap_bytes`, `total_bytes` and
+    /// `truncated`, see [`ReportNode`].
+    pub fn write_json<W: Write>(&self, writer: W) -> io::Result<()> {
+        let report = JsonReport {
+            version: REPORT_SCHEMA_VERSION,
+            total_bytes: self.total_bytes(),
+            records: self.records(),
+        };
+
+        serde_json::to_writer(writer, &report).map_err(io::Error::from)
+    }
+
+    /// Returns the report as a JSON document, see [`Report::write_json`].
+    pub fn to_json(&self) -> String {
+        let mut json = Vec::new();
+        self.write_json(&mut json)
+            .expect("writing to a `Vec` never fails");
+
+        String::from_utf8(json).expect("JSON is UTF-8")
+    }
+
+    /// Writes the report as CSV, with a header and a row per node, depth
+    /// first.
+    ///
+    /// The columns are the fields of the records of
+    /// [`Report::write_json`], in this order: `path`, `type_name`,
+    /// `variant` (empty if none), `count`, `inline_bytes`, `heap_bytes`,
+    /// `total_bytes` and `truncated`. New columns are only added at the
+    /// end.
+    pub fn write_csv<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "{}", CSV_HEADER)?;
+
+        for record in self.records() {
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{}",
+                csv_field(&record.path),
+                csv_field(record.type_name),
+                csv_field(record.variant.unwrap_or("")),
+                record.count,
+                record.inline_bytes,
+                record.heap_bytes,
+                record.total_bytes,
+                record.truncated,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the report as CSV, see [`Report::write_csv`].
+    pub fn to_csv(&self) -> String {
+        let mut csv = Vec::new();
+        self.write_csv(&mut csv)
+            .expect("writing to a `Vec` never fails");
+
+        String::from_utf8(csv).expect("CSV is UTF-8")
+    }
+}
@@ -0,0 +1,436 @@
+use crate::{MemoryUsage, MemoryUsageTracker, Tracker};
+use std::any;
+use std::collections::{HashMap, HashSet};
+use std::mem;
+
+/// Returns the [`Graph`] of the values owned and referenced by `value`.
+///
+/// It uses a default [`Tracker`], see [`Tracker::graph`] to configure it.
+///
+/// ```rust
+/// use std::rc::Rc;
+///
+/// let shared = Rc::new(vec![0u8; 64]);
+/// let value = (vec![shared.clone()], shared);
+///
+/// let graph = loupe::graph(&value);
+/// let retained = graph.retained_sizes();
+///
+/// // The shared `Vec` is kept alive by both fields, so dropping the
+/// // first one alone frees its buffer only.
+/// let buffer = graph.node_at(value.0.as_ptr() as *const ()).unwrap();
+/// assert_eq!(retained[buffer], graph.nodes()[buffer].bytes);
+/// ```
+pub fn graph<T>(value: &T) -> Graph
+where
+    T: MemoryUsage + ?Sized,
+{
+    Tracker::new().graph(value)
+}
+
+/// How a node of a [`Graph`] refers to another one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    /// The node owns a heap allocation, e.g. the buffer of a `Vec`.
+    Owned,
+    /// The node holds a shared pointer (`Rc`, `Arc`) to a shared
+    /// allocation.
+    Shared,
+    /// The node holds a reference to a value it doesn't own.
+    Borrowed,
+}
+
+/// A node of a [`Graph`]: the measured value, a heap allocation, or the
+/// pointee of a reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GraphNode {
+    /// The address identifying the node, as given to the
+    /// [`MemoryUsageTracker`].
+    pub address: *const (),
+    /// The name of the type of the measured value, of the value owning the
+    /// allocation, or of the pointee.
+    pub type_name: &'static str,
+    /// The size of the node in bytes, without what it refers to: the
+    /// inline size of a value, or the size of an allocation.
+    pub bytes: usize,
+    /// Whether values contained in the node have not been visited because
+    /// of the limits of the tracker, see [`Tracker::with_max_depth`] and
+    /// [`Tracker::with_max_values`].
+    pub truncated: bool,
+}
+
+/// An edge of a [`Graph`], from a node to a node it refers to. Nodes are
+/// given by their index in [`Graph::nodes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GraphEdge {
+    /// The node referring to the other one.
+    pub from: usize,
+    /// The node referred to.
+    pub to: usize,
+    /// How `from` refers to `to`.
+    pub kind: EdgeKind,
+}
+
+/// The graph of the values owned and referenced by a value, as visited
+/// while measuring it.
+///
+/// The nodes are the measured value, the heap allocations and the pointees
+/// of references, the values stored inline being part of the node storing
+/// them. A node reached several times, e.g. an allocation behind several
+/// `Rc`s, is a single node with several edges.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Graph {
+    nodes: Vec<GraphNode>,
+    edges: Vec<GraphEdge>,
+}
+
+impl Graph {
+    /// Returns the node of the measured value.
+    pub fn root(&self) -> &GraphNode {
+        &self.nodes[0]
+    }
+
+    /// Returns the nodes in the order they have been visited, the root
+    /// first.
+    pub fn nodes(&self) -> &[GraphNode] {
+        &self.nodes
+    }
+
+    /// Returns the edges in the order they have been visited. An edge is
+    /// given once, even if it has been visited several times.
+    pub fn edges(&self) -> &[GraphEdge] {
+        &self.edges
+    }
+
+    /// Returns the index of the node at `address`, if any.
+    pub fn node_at(&self, address: *const ()) -> Option<usize> {
+        self.nodes.iter().position(|node| node.address == address)
+    }
+
+    /// Returns the immediate dominator of every node, by index.
+    ///
+    /// A node dominates another one if every path of [`EdgeKind::Owned`]
+    /// and [`EdgeKind::Shared`] edges from the root to the latter goes
+    /// through it, i.e. if dropping it drops the other one. References
+    /// don't keep their pointee alive: a node only reachable through
+    /// references, like the root, has no dominator.
+    pub fn immediate_dominators(&self) -> Vec<Option<usize>> {
+        let dominators = Dominators::new(self);
+
+        dominators
+            .immediate
+            .iter()
+            .take(self.nodes.len())
+            .map(|dominator| dominator.filter(|&dominator| dominator != dominators.root))
+            .collect()
+    }
+
+    /// Returns the retained size of every node in bytes, by index, i.e.
+    /// the bytes that dropping it would free: its own bytes and the bytes
+    /// of the nodes it dominates, see [`Graph::immediate_dominators`].
+    ///
+    /// Every node is counted once, whatever the
+    /// [`SharedPolicy`](crate::SharedPolicy) of the tracker.
+    pub fn retained_sizes(&self) -> Vec<usize> {
+        let dominators = Dominators::new(self);
+        let mut retained = self.nodes.iter().map(|node| node.bytes).collect::<Vec<_>>();
+
+        // A node comes after the nodes it dominates in post-order.
+        for &node in &dominators.post_order {
+            match dominators.immediate[node] {
+                Some(dominator) if node != dominators.root && dominator != dominators.root => {
+                    retained[dominator] += retained[node];
+                }
+                _ => (),
+            }
+        }
+
+        retained
+    }
+}
+
+/// The dominator tree of a [`Graph`], computed with the algorithm of
+/// Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm".
+///
+/// A virtual root, after the nodes of the graph, refers to the root and to
+/// the nodes that aren't owned by any other node.
+struct Dominators {
+    root: usize,
+    /// The nodes in post-order, the virtual root last.
+    post_order: Vec<usize>,
+    /// The immediate dominator of every node, the virtual root being its
+    /// own dominator.
+    immediate: Vec<Option<usize>>,
+}
+
+impl Dominators {
+    fn new(graph: &Graph) -> Self {
+        let root = graph.nodes.len();
+        let mut successors = vec![Vec::new(); root + 1];
+        let mut predecessors = vec![Vec::new(); root + 1];
+
+        for edge in &graph.edges {
+            if edge.kind != EdgeKind::Borrowed {
+                successors[edge.from].push(edge.to);
+                predecessors[edge.to].push(edge.from);
+            }
+        }
+
+        // Depth first, from the virtual root. Nodes not reached yet are
+        // owned by nothing reached so far, they are given to the virtual
+        // root in the order they have been visited.
+        let mut post_order = Vec::with_capacity(root + 1);
+        let mut visited = vec![false; root + 1];
+
+        for node in 0..root {
+            if visited[node] {
+                continue;
+            }
+
+            predecessors[node].push(root);
+            visited[node] = true;
+
+            let mut stack = vec![(node, 0)];
+
+            while let Some((node, next)) = stack.last_mut() {
+                match successors[*node].get(*next) {
+                    Some(&successor) => {
+                        *next += 1;
+
+                        if !visited[successor] {
+                            visited[successor] = true;
+                            stack.push((successor, 0));
+                        }
+                    }
+                    None => {
+                        post_order.push(*node);
+                        stack.pop();
+                    }
+                }
+            }
+        }
+
+        post_order.push(root);
+
+        let mut order = vec![0; root + 1];
+        for (index, &node) in post_order.iter().enumerate() {
+            order[node] = index;
+        }
+
+        let mut immediate = vec![None; root + 1];
+        immediate[root] = Some(root);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+
+            for &node in post_order.iter().rev().skip(1) {
+                let mut processed = predecessors[node]
+                    .iter()
+                    .copied()
+                    .filter(|&predecessor| immediate[predecessor].is_some());
+                let first = processed.next().expect("a node has a visited predecessor");
+
+                let dominator = processed.fold(first, |mut a, mut b| {
+                    while a != b {
+                        while order[a] < order[b] {
+                            a = immediate[a].unwrap();
+                        }
+                        while order[b] < order[a] {
+                            b = immediate[b].unwrap();
+                        }
+                    }
+
+                    a
+                });
+
+                if immediate[node] != Some(dominator) {
+                    immediate[node] = Some(dominator);
+                    changed = true;
+                }
+            }
+        }
+
+        Self {
+            root,
+            post_order,
+            immediate,
+        }
+    }
+}
+
+/// A [`MemoryUsageTracker`] building a [`Graph`] from the heap allocations
+/// and references visited, forwarding everything to a [`Tracker`].
+struct GraphTracker<'a> {
+    tracker: &'a mut Tracker,
+    graph: Graph,
+    nodes_by_address: HashMap<*const (), usize>,
+    edges: HashSet<GraphEdge>,
+    /// The entered nodes, the root being the first one.
+    stack: Vec<usize>,
+    /// The node of the last visited shared pointer, until its allocation
+    /// is visited.
+    shared: Option<usize>,
+    /// The last measured value: its type name, inline and heap bytes.
+    measured: Option<(&'static str, usize, usize)>,
+}
+
+impl GraphTracker<'_> {
+    /// Returns the node at `address`, adding it if needed.
+    fn node_at(&mut self, address: *const (), type_name: &'static str) -> usize {
+        let nodes = &mut self.graph.nodes;
+
+        *self.nodes_by_address.entry(address).or_insert_with(|| {
+            nodes.push(GraphNode {
+                address,
+                type_name,
+                bytes: 0,
+                truncated: false,
+            });
+
+            nodes.len() - 1
+        })
+    }
+
+    /// Adds an edge from the current node to `to`, if it is new.
+    fn edge(&mut self, to: usize, kind: EdgeKind) {
+        let from = *self.stack.last().unwrap();
+        let edge = GraphEdge { from, to, kind };
+
+        if from != to && self.edges.insert(edge) {
+            self.graph.edges.push(edge);
+        }
+    }
+}
+
+impl MemoryUsageTracker for GraphTracker<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn sample_size(&self) -> Option<usize> {
+        // Every measured value is a node: sampling the elements of the
+        // collections would leave the other ones out of the graph.
+        None
+    }
+
+    fn heap_allocation(&mut self, address: *const (), type_name: &'static str, bytes: usize) {
+        // The allocation of a shared pointer is already linked to it.
+        let node = match self.shared.take() {
+            Some(node) if self.graph.nodes[node].address == address => node,
+            _ => {
+                let node = self.node_at(address, type_name);
+                self.edge(node, EdgeKind::Owned);
+
+                node
+            }
+        };
+
+        let node_ref = &mut self.graph.nodes[node];
+        node_ref.type_name = type_name;
+        node_ref.bytes = node_ref.bytes.max(bytes);
+
+        self.stack.push(node);
+        self.tracker.heap_allocation(address, type_name, bytes);
+    }
+
+    fn exit_heap_allocation(&mut self) {
+        self.stack.pop();
+        self.tracker.exit_heap_allocation();
+    }
+
+    fn measured_value(&mut self, type_name: &'static str, inline_bytes: usize, heap_bytes: usize) {
+        self.measured = Some((type_name, inline_bytes, heap_bytes));
+        self.tracker
+            .measured_value(type_name, inline_bytes, heap_bytes);
+    }
+
+    fn shared_reference(&mut self, address: *const ()) {
+        // The type is given by the allocation, if it is visited.
+        let node = self.node_at(address, "");
+        self.edge(node, EdgeKind::Shared);
+        self.shared = Some(node);
+
+        self.tracker.shared_reference(address);
+    }
+
+    fn exit_shared_reference(&mut self, bytes: usize) {
+        self.shared = None;
+        self.tracker.exit_shared_reference(bytes);
+    }
+
+    fn borrowed_reference(&mut self, address: *const (), type_name: &'static str) {
+        let node = self.node_at(address, type_name);
+        self.edge(node, EdgeKind::Borrowed);
+        self.stack.push(node);
+
+        self.tracker.borrowed_reference(address, type_name);
+    }
+
+    fn exit_borrowed_reference(&mut self, bytes: usize) {
+        let node = &mut self.graph.nodes[self.stack.pop().unwrap()];
+
+        // The pointee has just been measured if it is charged, its inline
+        // size is the one of the last measured value.
+        if let Some((type_name, inline_bytes, heap_bytes)) = self.measured {
+            if bytes > 0 && type_name == node.type_name && inline_bytes + heap_bytes == bytes {
+                node.bytes = node.bytes.max(inline_bytes);
+            }
+        }
+
+        self.tracker.exit_borrowed_reference(bytes);
+    }
+
+    fn record_truncated(&mut self, type_name: &'static str) {
+        let node = *self.stack.last().unwrap();
+        self.graph.nodes[node].truncated = true;
+
+        self.tracker.record_truncated(type_name);
+    }
+}
+
+impl Tracker {
+    /// Returns the [`Graph`] of the values owned and referenced by `value`,
+    /// measured with this tracker. See [`graph`].
+    ///
+    /// A value already tracked by this tracker is not visited again, so
+    /// its allocations are missing from the graph. The elements of the
+    /// collections are never sampled, see [`Tracker::with_sampling`].
+    pub fn graph<T>(&mut self, value: &T) -> Graph
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        let address = value as *const T as *const ();
+        let root = GraphNode {
+            address,
+            type_name: any::type_name::<T>(),
+            bytes: mem::size_of_val(value),
+            truncated: false,
+        };
+
+        let mut tracker = GraphTracker {
+            tracker: self,
+            graph: Graph {
+                nodes: vec![root],
+                edges: Vec::new(),
+            },
+            nodes_by_address: std::iter::once((address, 0)).collect(),
+            edges: HashSet::new(),
+            stack: vec![0],
+            shared: None,
+            measured: None,
+        };
+
+        value.size_of_val(&mut tracker);
+
+        tracker.graph
+    }
+}
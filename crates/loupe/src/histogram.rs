@@ -0,0 +1,170 @@
+use crate::{MemoryUsage, MemoryUsageTracker, Tracker};
+use std::any;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+
+/// Returns the [`Histogram`] of the values contained in `value`, by type.
+///
+/// It uses a default [`Tracker`], see [`Tracker::histogram`] to configure
+/// it.
+///
+/// ```rust
+/// let names = vec![String::from("a"), String::from("bc")];
+/// let histogram = loupe::histogram(&names);
+///
+/// let strings = histogram.get(std::any::type_name::<String>()).unwrap();
+/// assert_eq!(strings.count, 2);
+/// assert_eq!(strings.heap_bytes, 3);
+/// ```
+pub fn histogram<T>(value: &T) -> Histogram
+where
+    T: MemoryUsage + ?Sized,
+{
+    Tracker::new().histogram(value)
+}
+
+/// The values of a given type in a [`Histogram`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistogramEntry {
+    /// The name of the type, as given by `std::any::type_name`.
+    pub type_name: &'static str,
+    /// The number of values of this type.
+    pub count: usize,
+    /// The sum of the inline sizes of the values.
+    pub inline_bytes: usize,
+    /// The sum of the bytes owned by the values outside of their inline
+    /// size.
+    pub heap_bytes: usize,
+}
+
+impl HistogramEntry {
+    /// Returns the sum of the sizes of the values.
+    pub fn total_bytes(&self) -> usize {
+        self.inline_bytes + self.heap_bytes
+    }
+}
+
+/// The number and the size of the values contained in a value, by type,
+/// like the "by type" view of a heap profiler.
+///
+/// A value contains other values, e.g. a `Vec<String>` contains `String`s,
+/// so the bytes of a value are also counted by the values containing it.
+/// Only the values that are measured are counted: a value visited through
+/// several references is counted once, and the content of the types that
+/// aren't measured value by value, like the bytes of a `String`, is not
+/// counted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    entries: Vec<HistogramEntry>,
+}
+
+impl Histogram {
+    /// Returns the entries, sorted by total size, the largest first.
+    pub fn entries(&self) -> &[HistogramEntry] {
+        &self.entries
+    }
+
+    /// Returns the entry of the type named `type_name`.
+    pub fn get(&self, type_name: &str) -> Option<&HistogramEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.type_name == type_name)
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(
+                formatter,
+                "{}: {} values, {} bytes ({} inline, {} heap)",
+                entry.type_name,
+                entry.count,
+                entry.total_bytes(),
+                entry.inline_bytes,
+                entry.heap_bytes,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A [`MemoryUsageTracker`] building a [`Histogram`] from the measured
+/// values, forwarding everything else to a [`Tracker`].
+struct HistogramTracker<'a> {
+    tracker: &'a mut Tracker,
+    entries: HashMap<&'static str, HistogramEntry>,
+}
+
+impl MemoryUsageTracker for HistogramTracker<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn sample_size(&self) -> Option<usize> {
+        // The values are counted as they are measured: sampling the elements
+        // of the collections would only count the sampled ones.
+        None
+    }
+
+    fn measured_value(&mut self, type_name: &'static str, inline_bytes: usize, heap_bytes: usize) {
+        let entry = self
+            .entries
+            .entry(type_name)
+            .or_insert_with(|| HistogramEntry {
+                type_name,
+                count: 0,
+                inline_bytes: 0,
+                heap_bytes: 0,
+            });
+
+        entry.count += 1;
+        entry.inline_bytes += inline_bytes;
+        entry.heap_bytes += heap_bytes;
+
+        self.tracker
+            .measured_value(type_name, inline_bytes, heap_bytes);
+    }
+}
+
+impl Tracker {
+    /// Returns the [`Histogram`] of the values contained in `value`, by
+    /// type, measured with this tracker. See [`histogram`].
+    ///
+    /// The elements of the collections are never sampled, see
+    /// [`Tracker::with_sampling`].
+    pub fn histogram<T>(&mut self, value: &T) -> Histogram
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        let mut tracker = HistogramTracker {
+            tracker: self,
+            entries: HashMap::new(),
+        };
+
+        // The outermost value isn't contained in another one, it is
+        // counted here.
+        let size = value.size_of_val(&mut tracker);
+        let inline_bytes = mem::size_of_val(value);
+        tracker.measured_value(any::type_name::<T>(), inline_bytes, size - inline_bytes);
+
+        let mut entries = tracker.entries.into_values().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            b.total_bytes()
+                .cmp(&a.total_bytes())
+                .then(a.type_name.cmp(b.type_name))
+        });
+
+        Histogram { entries }
+    }
+}
@@ -1,3 +1,31 @@
+mod breakdown;
+mod budget;
+mod counting_allocator;
+mod diff;
+mod dot;
+#[cfg(feature = "export")]
+mod export;
+mod graph;
+mod histogram;
 mod memory_usage;
+mod report;
+mod tracker;
+mod usable_size;
 
-pub use memory_usage::{MemoryUsage, MemoryUsageTracker, POINTER_BYTE_SIZE};
+pub use breakdown::{breakdown, Breakdown};
+pub use counting_allocator::{check_heap_size, AllocationScope, CountingAllocator, HeapSizeCheck};
+pub use diff::{DiffEntry, ReportDiff};
+pub use dot::DotOptions;
+#[cfg(feature = "export")]
+pub use export::REPORT_SCHEMA_VERSION;
+pub use graph::{graph, EdgeKind, Graph, GraphEdge, GraphNode};
+pub use histogram::{histogram, Histogram, HistogramEntry};
+pub use memory_usage::{
+    LockPolicy, MemoryUsage, MemoryUsageTracker, SharedPolicy, POINTER_BYTE_SIZE,
+};
+pub use report::{report, Report, ReportNode};
+pub use tracker::{MeasureError, ShallowTracker, Tracker};
+
+#[cfg(test)]
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator::system();
@@ -1,6 +1,31 @@
-#[cfg(test)]
-use std::collections::BTreeSet;
-use std::mem;
+use crate::breakdown::ReferencedBytesTracker;
+use std::alloc::Layout;
+use std::any;
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell, Ref, RefCell, RefMut, UnsafeCell};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, LinkedList, VecDeque};
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::marker::PhantomPinned;
+use std::mem::{self, ManuallyDrop, MaybeUninit};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{
+    NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize, NonZeroU128,
+    NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize, Wrapping,
+};
+use std::ops::{Range, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive};
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::rc::{self, Rc};
+use std::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI8, AtomicIsize, AtomicPtr, AtomicU16, AtomicU32,
+    AtomicU8, AtomicUsize,
+};
+#[cfg(target_has_atomic = "64")]
+use std::sync::atomic::{AtomicI64, AtomicU64};
+use std::sync::{self, Arc, Mutex, OnceLock, RwLock, TryLockError, TryLockResult};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 pub const POINTER_BYTE_SIZE: usize = if cfg!(target_pointer_width = "16") {
     2
@@ -10,9 +35,334 @@ pub const POINTER_BYTE_SIZE: usize = if cfg!(target_pointer_width = "16") {
     8
 };
 
+/// Tracks the values visited by [`MemoryUsage::size_of_val`], so that
+/// each of them is measured only once.
+///
+/// It is also a visitor: the implementations of [`MemoryUsage`] and the
+/// derive macro call its other methods while walking a value, so that a
+/// tracker can attribute the bytes to fields, heap allocations and
+/// references without changing how they are measured. They all do nothing
+/// by default, unless the tracker wraps another one, see
+/// [`MemoryUsageTracker::inner`].
 pub trait MemoryUsageTracker {
     /// When first called on a given address returns true, else returns false.
     fn track(&mut self, address: *const ()) -> bool;
+
+    /// Returns the tracker wrapped by this one, if any.
+    ///
+    /// All the other methods but [`MemoryUsageTracker::track`] forward to
+    /// it by default, so that a tracker adding a feature to another one,
+    /// like [`ShallowTracker`](crate::ShallowTracker), only overrides the
+    /// methods it needs. It returns `None` by default.
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        None
+    }
+
+    /// Returns the tracker wrapped by this one, if any, see
+    /// [`MemoryUsageTracker::inner`]. Both must return the same tracker.
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        None
+    }
+
+    /// When first called on a value of `size` bytes at `address` returns
+    /// true, else returns false.
+    ///
+    /// The implementations of this crate call it rather than
+    /// [`MemoryUsageTracker::track`] for the values they reach through
+    /// references and shared pointers. A struct and its first field, or a
+    /// zero-sized value and the value next to it, share an address but not
+    /// a size, so a tracker keying on both measures each of them, even if
+    /// it means counting the field twice rather than skipping the struct.
+    /// By default, it calls `track(address)`, which keys on the address
+    /// only.
+    fn track_value(&mut self, address: *const (), size: usize) -> bool {
+        match self.inner_mut() {
+            Some(inner) => inner.track_value(address, size),
+            None => self.track(address),
+        }
+    }
+
+    /// Called by collections with the number of bytes they have reserved
+    /// but do not currently use, e.g. the spare capacity of a `Vec`.
+    ///
+    /// Those bytes are already included in the size returned by
+    /// [`MemoryUsage::size_of_val`]; this only reports them separately.
+    fn unused_capacity(&mut self, bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.unused_capacity(bytes);
+        }
+    }
+
+    /// Returns how the allocations behind shared pointers (`Rc`, `Arc`)
+    /// must be accounted. See [`SharedPolicy`].
+    fn shared_policy(&self) -> SharedPolicy {
+        self.inner()
+            .map_or(SharedPolicy::FirstOwner, |inner| inner.shared_policy())
+    }
+
+    /// Called the first time a shared allocation is visited, with its size
+    /// in bytes, refcount header included.
+    fn shared_allocation(&mut self, address: *const (), bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.shared_allocation(address, bytes);
+        }
+    }
+
+    /// Returns the size previously given to
+    /// [`MemoryUsageTracker::shared_allocation`] for `address`, if the
+    /// tracker remembers it. It is required by [`SharedPolicy::EvenSplit`].
+    fn size_of_shared_allocation(&self, address: *const ()) -> Option<usize> {
+        self.inner()
+            .and_then(|inner| inner.size_of_shared_allocation(address))
+    }
+
+    /// Returns what to do when a value to measure is behind a lock that
+    /// is held. See [`LockPolicy`].
+    fn lock_policy(&self) -> LockPolicy {
+        self.inner()
+            .map_or(LockPolicy::Skip, |inner| inner.lock_policy())
+    }
+
+    /// Called when a value cannot be measured, e.g. because it is behind a
+    /// lock that is held, with the name of the type that contains it. The
+    /// inline size of the container is still counted.
+    fn record_unmeasured(&mut self, type_name: &'static str) {
+        if let Some(inner) = self.inner_mut() {
+            inner.record_unmeasured(type_name);
+        }
+    }
+
+    /// Called before measuring a field named `name` of type `type_name`.
+    ///
+    /// Fields can nest: the fields of a field are entered before it is
+    /// exited. The derive macro calls it for every measured field.
+    fn enter_field(&mut self, name: &'static str, type_name: &'static str) {
+        if let Some(inner) = self.inner_mut() {
+            inner.enter_field(name, type_name);
+        }
+    }
+
+    /// Called when the value being measured is an `enum` in the variant
+    /// named `name`, before its fields are entered.
+    fn variant(&mut self, name: &'static str) {
+        if let Some(inner) = self.inner_mut() {
+            inner.variant(name);
+        }
+    }
+
+    /// Called after measuring the last entered field, with its inline size
+    /// and the number of bytes it owns outside of it.
+    fn exit_field(&mut self, inline_bytes: usize, heap_bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.exit_field(inline_bytes, heap_bytes);
+        }
+    }
+
+    /// Called when visiting a heap allocation owned by the measured value,
+    /// e.g. the buffer of a `Vec` or the pointee of a `Box`, with an
+    /// address identifying it, the name of the type owning it, e.g.
+    /// `alloc::vec::Vec<u8>`, and its size in bytes.
+    ///
+    /// The values stored in the allocation are visited before the matching
+    /// [`MemoryUsageTracker::exit_heap_allocation`]. Zero-sized allocations
+    /// are not visited, neither are the allocations whose address is
+    /// unknown, like the table of an empty `HashMap` with spare capacity.
+    /// The nodes of a `BTreeMap` are visited as a single allocation.
+    fn heap_allocation(&mut self, address: *const (), type_name: &'static str, bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.heap_allocation(address, type_name, bytes);
+        }
+    }
+
+    /// Called after visiting the values stored in the last visited heap
+    /// allocation.
+    fn exit_heap_allocation(&mut self) {
+        if let Some(inner) = self.inner_mut() {
+            inner.exit_heap_allocation();
+        }
+    }
+
+    /// Returns the size of the heap block allocated for `layout` at
+    /// `address`, i.e. what the allocator has reserved for it, which can
+    /// be larger than `layout`.
+    ///
+    /// It is called by the implementations that know where their blocks
+    /// start: `Vec`, `BinaryHeap`, `String`, `Box`, etc. The tables of a
+    /// `HashMap`, the nodes of a `BTreeMap` and the allocations of `Rc` and
+    /// `Arc` don't expose their address, so their size is always
+    /// estimated. By default, it returns the size of `layout`, see
+    /// [`Tracker::with_usable_sizes`](crate::Tracker::with_usable_sizes).
+    fn allocation_size(&self, address: *const (), layout: Layout) -> usize {
+        self.inner().map_or(layout.size(), |inner| {
+            inner.allocation_size(address, layout)
+        })
+    }
+
+    /// Called after measuring a value of type `type_name` contained in the
+    /// measured value, e.g. a field, an element of a collection, or the
+    /// pointee of a `Box`, a shared pointer or a reference, with its inline
+    /// size and the number of bytes it owns outside of it.
+    ///
+    /// It is called once per measured value, the outermost one excepted,
+    /// and after the values it contains.
+    fn measured_value(&mut self, type_name: &'static str, inline_bytes: usize, heap_bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.measured_value(type_name, inline_bytes, heap_bytes);
+        }
+    }
+
+    /// Called every time a shared pointer (`Rc`, `Arc`) is visited, with
+    /// the address of its value. The first time an address is visited, it
+    /// is followed by a [`MemoryUsageTracker::heap_allocation`] for the
+    /// shared allocation.
+    fn shared_reference(&mut self, address: *const ()) {
+        if let Some(inner) = self.inner_mut() {
+            inner.shared_reference(address);
+        }
+    }
+
+    /// Called after measuring the last visited shared pointer, with the
+    /// bytes of the shared allocation charged to it according to the
+    /// [`SharedPolicy`]. The size of the pointer itself is excluded.
+    fn exit_shared_reference(&mut self, bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.exit_shared_reference(bytes);
+        }
+    }
+
+    /// Called every time a reference (`&T`, `&mut T`, `Ref`, `RefMut`, a
+    /// borrowed `Cow`) is visited, with the address of its pointee and the
+    /// name of its type.
+    fn borrowed_reference(&mut self, address: *const (), type_name: &'static str) {
+        if let Some(inner) = self.inner_mut() {
+            inner.borrowed_reference(address, type_name);
+        }
+    }
+
+    /// Called after measuring the last visited reference, with the bytes
+    /// of the pointee charged to it, i.e. zero if the pointee has already
+    /// been visited. The size of the reference itself is excluded.
+    fn exit_borrowed_reference(&mut self, bytes: usize) {
+        if let Some(inner) = self.inner_mut() {
+            inner.exit_borrowed_reference(bytes);
+        }
+    }
+
+    /// Returns whether the traversal must stop, e.g. because a budget has
+    /// been exceeded or because the value is too deep.
+    ///
+    /// The implementations of this crate call it once before measuring
+    /// each value contained in the measured value, e.g. each element of a
+    /// collection or the pointee of a `Box`, and don't visit it if it
+    /// returns true. The value is then reported to
+    /// [`MemoryUsageTracker::record_truncated`], the size returned by
+    /// [`MemoryUsage::size_of_val`] being a lower bound. It returns false
+    /// by default.
+    fn should_stop(&mut self) -> bool {
+        self.inner_mut().is_some_and(|inner| inner.should_stop())
+    }
+
+    /// Called when a value of type `type_name` is not visited because the
+    /// traversal has stopped, see [`MemoryUsageTracker::should_stop`]. The
+    /// other values of the same collection are not visited either.
+    fn record_truncated(&mut self, type_name: &'static str) {
+        if let Some(inner) = self.inner_mut() {
+            inner.record_truncated(type_name);
+        }
+    }
+
+    /// Returns the maximum number of elements of a collection to measure,
+    /// if the collections must be sampled rather than measured element by
+    /// element. It returns `None` by default, i.e. every element is
+    /// measured.
+    ///
+    /// The elements of a larger collection are sampled with an even
+    /// stride, and what they own outside of the collection is
+    /// extrapolated to all the elements, the pointees of their shared
+    /// pointers and references excepted. The other elements are not
+    /// visited. The sampling is reported to
+    /// [`MemoryUsageTracker::sampled_elements`].
+    fn sample_size(&self) -> Option<usize> {
+        self.inner().and_then(|inner| inner.sample_size())
+    }
+
+    /// Called after sampling the elements of type `type_name` of a
+    /// collection, see [`MemoryUsageTracker::sample_size`], with the number
+    /// of `sampled` elements out of `len`, and the standard error of the
+    /// extrapolated bytes, i.e. the standard deviation of the estimate.
+    fn sampled_elements(
+        &mut self,
+        type_name: &'static str,
+        sampled: usize,
+        len: usize,
+        standard_error: f64,
+    ) {
+        if let Some(inner) = self.inner_mut() {
+            inner.sampled_elements(type_name, sampled, len, standard_error);
+        }
+    }
+}
+
+/// Accounting policy for the allocations owned by several shared pointers,
+/// like `Rc` and `Arc`.
+///
+/// Whatever the policy, a shared allocation is measured only once per
+/// tracker.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SharedPolicy {
+    /// The first owner to be visited is charged the whole allocation, the
+    /// other owners are charged nothing.
+    #[default]
+    FirstOwner,
+
+    /// Every owner to be visited is charged the size of the allocation
+    /// divided by its number of strong owners.
+    ///
+    /// The tracker must remember the size of the allocations, see
+    /// [`MemoryUsageTracker::size_of_shared_allocation`], otherwise only the
+    /// first owner is charged its share.
+    EvenSplit,
+
+    /// No owner is charged. The allocation is only reported to
+    /// [`MemoryUsageTracker::shared_allocation`].
+    Unowned,
+}
+
+/// What to do when a value to measure is behind a lock (`Mutex`, `RwLock`,
+/// `RefCell`) that is held.
+///
+/// Measuring never deadlocks, even when the lock is held by the thread
+/// doing the measurement. Poisoned locks are measured as usual.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LockPolicy {
+    /// Waits for the lock to be released, at most for the given duration.
+    /// If the lock is still held afterwards, the value is skipped as with
+    /// [`LockPolicy::Skip`]. With `Duration::MAX`, it waits for as long as
+    /// it takes.
+    ///
+    /// A `RefCell` can only be borrowed by the current thread, waiting
+    /// cannot help, so it is skipped right away.
+    Block(Duration),
+
+    /// Doesn't measure the value, and reports it to
+    /// [`MemoryUsageTracker::record_unmeasured`].
+    #[default]
+    Skip,
+
+    /// Same as [`LockPolicy::Skip`], but the tracker is expected to turn
+    /// the unmeasured values into an error.
+    ///
+    /// Only [`Tracker::measure`](crate::Tracker::measure),
+    /// [`Tracker::measure_within`](crate::Tracker::measure_within) and
+    /// [`Tracker::measure_breakdown`](crate::Tracker::measure_breakdown)
+    /// return an error. The other entry points, like
+    /// [`Tracker::report`](crate::Tracker::report),
+    /// [`Tracker::histogram`](crate::Tracker::histogram) or
+    /// [`Tracker::graph`](crate::Tracker::graph), behave as with
+    /// [`LockPolicy::Skip`]: check
+    /// [`Tracker::unmeasured_values`](crate::Tracker::unmeasured_values)
+    /// afterwards.
+    Fail,
 }
 
 impl MemoryUsageTracker for std::collections::BTreeSet<*const ()> {
@@ -32,6 +382,9 @@ pub trait MemoryUsage {
     ///
     /// Recursively visits the value and any children returning the sum of their
     /// sizes. The size always includes any tail padding if applicable.
+    ///
+    /// See [`breakdown`](crate::breakdown) to split the size into inline,
+    /// heap and shared bytes.
     fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize;
 }
 
@@ -45,6 +398,26 @@ macro_rules! assert_size_of_val_eq {
     };
 }
 
+/// A tracker that sums the bytes reported by
+/// [`MemoryUsageTracker::unused_capacity`].
+#[cfg(test)]
+#[derive(Default)]
+struct UnusedCapacityTracker {
+    visited: BTreeSet<*const ()>,
+    unused: usize,
+}
+
+#[cfg(test)]
+impl MemoryUsageTracker for UnusedCapacityTracker {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.visited.track(address)
+    }
+
+    fn unused_capacity(&mut self, bytes: usize) {
+        self.unused += bytes;
+    }
+}
+
 // Primitive types
 macro_rules! impl_memory_usage_for_primitive {
     ( $type:ty ) => {
@@ -61,7 +434,7 @@ macro_rules! impl_memory_usage_for_primitive {
 }
 
 impl_memory_usage_for_primitive!(
-    bool, char, f32, f64, i8, i16, i32, i64, isize, u8, u16, u32, u64, usize
+    bool, char, f32, f64, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize
 );
 
 #[cfg(test)]
@@ -90,11 +463,13 @@ mod test_primitive_types {
         test_i16: (1i16) == 2;
         test_i32: (1i32) == 4;
         test_i64: (1i64) == 8;
+        test_i128: (1i128) == 16;
         test_isize: (1isize) == POINTER_BYTE_SIZE;
         test_u8: (1u8) == 1;
         test_u16: (1u16) == 2;
         test_u32: (1u32) == 4;
         test_u64: (1u64) == 8;
+        test_u128: (1u128) == 16;
         test_usize: (1usize) == POINTER_BYTE_SIZE;
     );
 }
@@ -103,25 +478,34 @@ mod test_primitive_types {
 // Pointers aren't necessarily safe to dereference, even if they're nonnull.
 
 // Reference types.
-impl<T: MemoryUsage> MemoryUsage for &T {
+/// Returns the size of the pointee of a reference, if it hasn't been
+/// tracked yet.
+fn size_of_borrowed<T: MemoryUsage + ?Sized>(
+    pointee: &T,
+    tracker: &mut dyn MemoryUsageTracker,
+) -> usize {
+    let address = pointee as *const T as *const ();
+    tracker.borrowed_reference(address, any::type_name::<T>());
+
+    let size = if tracker.track_value(address, mem::size_of_val(pointee)) {
+        mem::size_of_val(pointee) + size_of_heap_of_value(pointee, tracker)
+    } else {
+        0
+    };
+    tracker.exit_borrowed_reference(size);
+
+    size
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for &T {
     fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
-        mem::size_of_val(self)
-            + if tracker.track(*self as *const T as *const ()) {
-                MemoryUsage::size_of_val(*self, tracker)
-            } else {
-                0
-            }
+        mem::size_of::<&T>() + size_of_borrowed(*self, tracker)
     }
 }
 
-impl<T: MemoryUsage> MemoryUsage for &mut T {
+impl<T: MemoryUsage + ?Sized> MemoryUsage for &mut T {
     fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
-        mem::size_of_val(self)
-            + if tracker.track(*self as *const T as *const ()) {
-                MemoryUsage::size_of_val(*self, tracker)
-            } else {
-                0
-            }
+        mem::size_of::<&mut T>() + size_of_borrowed(*self, tracker)
     }
 }
 
@@ -140,171 +524,2511 @@ mod test_reference_types {
     }
 }
 
-// slices
-impl<T: MemoryUsage> MemoryUsage for [T] {
-    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
-        mem::size_of_val(self)
-            + self
-                .iter()
-                .map(|v| MemoryUsage::size_of_val(v, tracker) - mem::size_of_val(v))
-                .sum::<usize>()
+/// Returns the sum of the sizes of `values` minus their inline sizes, i.e.
+/// what they own outside of the memory that stores them.
+///
+/// This is used by containers that already account for the memory that
+/// stores their elements (the inline array, the heap buffer, etc.). The
+/// elements are sampled if the tracker asks for it, see
+/// [`MemoryUsageTracker::sample_size`].
+fn size_of_heap_of_elements<'a, T, I>(values: I, tracker: &mut dyn MemoryUsageTracker) -> usize
+where
+    T: MemoryUsage + 'a,
+    I: ExactSizeIterator<Item = &'a T>,
+{
+    let len = values.len();
+
+    match tracker.sample_size() {
+        Some(sample_size) if sample_size > 0 && len > sample_size => {
+            size_of_heap_of_sampled_elements(values, len.div_ceil(sample_size), tracker)
+        }
+        _ => size_of_heap_of_all_elements(values, tracker),
     }
 }
 
-// arrays
-impl<T: MemoryUsage, const N: usize> MemoryUsage for [T; N] {
-    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
-        mem::size_of_val(self)
-            + self
-                .iter()
-                .map(|v| MemoryUsage::size_of_val(v, tracker) - mem::size_of_val(v))
-                .sum::<usize>()
+/// Returns the sum of the sizes of `values` minus their inline sizes, see
+/// [`size_of_heap_of_elements`], measuring every one of them.
+fn size_of_heap_of_all_elements<'a, T, I>(values: I, tracker: &mut dyn MemoryUsageTracker) -> usize
+where
+    T: MemoryUsage + 'a,
+    I: Iterator<Item = &'a T>,
+{
+    let mut size = 0;
+
+    for value in values {
+        match try_size_of_heap_of_value(value, tracker) {
+            Some(heap_bytes) => size += heap_bytes,
+            None => break,
+        }
     }
+
+    size
 }
 
-// strs
-/*
-impl MemoryUsage for str {
-    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
-        self.as_bytes().size_of()
+/// Returns the sum of the sizes of `values` minus their inline sizes, see
+/// [`size_of_heap_of_elements`], extrapolated from one value every
+/// `stride` values.
+///
+/// Only the bytes owned by the sampled values are extrapolated, as the
+/// mean of the sampled values times the number of values. Its standard
+/// error is computed from the variance of the sampled values, with the
+/// finite population correction. The bytes charged to the shared pointers
+/// and references of the sampled values are added as is: they are
+/// measured once per tracker, extrapolating them would count the
+/// pointees shared by several values several times.
+fn size_of_heap_of_sampled_elements<'a, T, I>(
+    values: I,
+    stride: usize,
+    tracker: &mut dyn MemoryUsageTracker,
+) -> usize
+where
+    T: MemoryUsage + 'a,
+    I: ExactSizeIterator<Item = &'a T>,
+{
+    let len = values.len();
+    let mut sampled = 0;
+    let mut sum = 0;
+    let mut sum_of_squares = 0.0;
+    let mut tracker = ReferencedBytesTracker::new(tracker);
+
+    for value in values.step_by(stride) {
+        let referenced_bytes = tracker.bytes();
+
+        match try_size_of_heap_of_value(value, &mut tracker) {
+            Some(heap_bytes) => {
+                let owned_bytes = heap_bytes - (tracker.bytes() - referenced_bytes);
+
+                sampled += 1;
+                sum += owned_bytes;
+                sum_of_squares += (owned_bytes as f64).powi(2);
+            }
+
+            // The size is a lower bound once the traversal has stopped,
+            // it is not extrapolated.
+            None => return sum + tracker.bytes(),
+        }
     }
+
+    let estimate = (sum as u128 * len as u128 / sampled as u128) as usize;
+
+    let mean = sum as f64 / sampled as f64;
+    let variance = if sampled > 1 {
+        (sum_of_squares - sampled as f64 * mean * mean).max(0.0) / (sampled - 1) as f64
+    } else {
+        0.0
+    };
+    let standard_error = len as f64
+        * (variance / sampled as f64).sqrt()
+        * (1.0 - sampled as f64 / len as f64).sqrt();
+
+    tracker.sampled_elements(any::type_name::<T>(), sampled, len, standard_error);
+
+    estimate + tracker.bytes()
 }
-*/
 
-// TODO: tuples
+/// Returns the size of `value` minus its inline size, i.e. what it owns
+/// outside of the memory that stores it, and reports it to the tracker
+/// with [`MemoryUsageTracker::measured_value`].
+///
+/// It returns zero without visiting `value` if the traversal must stop,
+/// see [`MemoryUsageTracker::should_stop`].
+fn size_of_heap_of_value<T>(value: &T, tracker: &mut dyn MemoryUsageTracker) -> usize
+where
+    T: MemoryUsage + ?Sized,
+{
+    try_size_of_heap_of_value(value, tracker).unwrap_or(0)
+}
+
+/// Same as [`size_of_heap_of_value`], but returns `None` if the traversal
+/// must stop, once the value has been reported as truncated.
+fn try_size_of_heap_of_value<T>(value: &T, tracker: &mut dyn MemoryUsageTracker) -> Option<usize>
+where
+    T: MemoryUsage + ?Sized,
+{
+    if tracker.should_stop() {
+        tracker.record_truncated(any::type_name::<T>());
 
-/// Standard library types
+        return None;
+    }
 
-// TODO: Arc
+    let inline_bytes = mem::size_of_val(value);
+    let heap_bytes = MemoryUsage::size_of_val(value, tracker) - inline_bytes;
+    tracker.measured_value(any::type_name::<T>(), inline_bytes, heap_bytes);
 
-//impl<T: MemoryUsage> MemoryUsage for Box<T> {
-//}
+    Some(heap_bytes)
+}
 
-// Cell
+/// Returns the size of the heap block allocated for `layout` at `address`,
+/// see [`MemoryUsageTracker::allocation_size`]. The bytes reserved by the
+/// allocator beyond `layout` are reported as unused capacity.
+fn size_of_block(
+    address: *const u8,
+    layout: Layout,
+    tracker: &mut dyn MemoryUsageTracker,
+) -> usize {
+    if layout.size() == 0 {
+        return 0;
+    }
 
-// Is a Pin always dereferenceable?
-//impl<T: MemoryUsage> MemoryUsage for Pin<T> {
-//}
+    let size = tracker
+        .allocation_size(address as *const (), layout)
+        .max(layout.size());
+    tracker.unused_capacity(size - layout.size());
 
-// TODO: Mutex
+    size
+}
 
-// TODO: NonNull might be possible when '*const T' is MemoryUsage.
+/// Returns the size of a heap allocation of `bytes` bytes owned by a value
+/// of type `type_name`, plus what its content owns outside of it, as
+/// returned by `content`.
+///
+/// The allocation is visited around `content` if it isn't empty and its
+/// `address` is known, see [`MemoryUsageTracker::heap_allocation`].
+fn size_of_heap_allocation<F>(
+    address: Option<*const ()>,
+    type_name: &'static str,
+    bytes: usize,
+    tracker: &mut dyn MemoryUsageTracker,
+    content: F,
+) -> usize
+where
+    F: FnOnce(&mut dyn MemoryUsageTracker) -> usize,
+{
+    match address {
+        Some(address) if bytes > 0 => {
+            tracker.heap_allocation(address, type_name, bytes);
+            let heap = content(tracker);
+            tracker.exit_heap_allocation();
 
-impl<T: MemoryUsage> MemoryUsage for Option<T> {
-    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
-        mem::size_of_val(self)
-            + self
-                .iter()
-                .map(|v| MemoryUsage::size_of_val(v, tracker))
-                .sum::<usize>()
+            bytes + heap
+        }
+        _ => bytes + content(tracker),
     }
 }
 
-// TODO: Rc
+// slices
+impl<T: MemoryUsage> MemoryUsage for [T] {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(self.iter(), tracker)
+    }
+}
 
-// TODO: Ref, RefCell, RefMut
+// arrays
+impl<T: MemoryUsage, const N: usize> MemoryUsage for [T; N] {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(self.iter(), tracker)
+    }
+}
 
-//impl<T: MemoryUsage, E: MemoryUsage> MemoryUsage for Result<T, E> {
-//}
+// strs
+macro_rules! impl_memory_usage_for_unsized_string {
+    ( $( $type:ty ),+ ) => {
+        $(
+            impl MemoryUsage for $type {
+                fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+                    mem::size_of_val(self)
+                }
+            }
+        )+
+    }
+}
 
-// TODO: RwLock
+impl_memory_usage_for_unsized_string!(str, CStr, OsStr, Path);
 
-// string?
+/// Returns the size of the buffer of an owned string of type `type_name`,
+/// which holds bytes only.
+fn size_of_string_buffer(
+    address: *const u8,
+    type_name: &'static str,
+    bytes: usize,
+    tracker: &mut dyn MemoryUsageTracker,
+) -> usize {
+    let layout = Layout::from_size_align(bytes, 1).expect("a string has a valid layout");
+    let bytes = size_of_block(address, layout, tracker);
 
-// TODO: UnsafeCell
+    size_of_heap_allocation(Some(address as *const ()), type_name, bytes, tracker, |_| 0)
+}
 
-impl<T: MemoryUsage> MemoryUsage for Vec<T> {
+// Owned strings. Their buffer is `capacity` bytes long, whether the
+// bytes are used or not.
+impl MemoryUsage for String {
     fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        tracker.unused_capacity(self.capacity() - self.len());
+
         mem::size_of_val(self)
-            + self
-                .iter()
-                .map(|v| MemoryUsage::size_of_val(v, tracker))
-                .sum::<usize>()
+            + size_of_string_buffer(
+                self.as_ptr(),
+                any::type_name::<Self>(),
+                self.capacity(),
+                tracker,
+            )
     }
 }
 
-impl<T> MemoryUsage for std::marker::PhantomData<T> {
-    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
-        0
+impl MemoryUsage for OsString {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        tracker.unused_capacity(self.capacity() - self.len());
+
+        mem::size_of_val(self)
+            + size_of_string_buffer(
+                self.as_encoded_bytes().as_ptr(),
+                any::type_name::<Self>(),
+                self.capacity(),
+                tracker,
+            )
     }
 }
 
-// TODO: PhantomPinned?
+impl MemoryUsage for PathBuf {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        tracker.unused_capacity(self.capacity() - self.as_os_str().len());
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::BTreeSet;
+        mem::size_of_val(self)
+            + size_of_string_buffer(
+                self.as_os_str().as_encoded_bytes().as_ptr(),
+                any::type_name::<Self>(),
+                self.capacity(),
+                tracker,
+            )
+    }
+}
 
-    #[derive(Copy, Clone)]
-    struct TestMemoryUsage {
-        // Must be greater than or equal to mem::size_of::<TestMemoryUsage>() or else MemoryUsage may overflow.
-        pub size_to_report: usize,
+// A `CString` is a boxed slice, it has no spare capacity.
+impl MemoryUsage for CString {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + size_of_string_buffer(
+                self.as_ptr() as *const u8,
+                any::type_name::<Self>(),
+                self.as_bytes_with_nul().len(),
+                tracker,
+            )
     }
-    impl MemoryUsage for TestMemoryUsage {
-        fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
-            // Try to prevent buggy tests before they're hard to debug.
-            assert!(self.size_to_report >= mem::size_of::<TestMemoryUsage>());
-            self.size_to_report
+}
+
+// A borrowed `Cow` is a reference, an owned `Cow` is its owned value.
+impl<B> MemoryUsage for Cow<'_, B>
+where
+    B: MemoryUsage + ToOwned + ?Sized,
+    B::Owned: MemoryUsage,
+{
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        match self {
+            Cow::Borrowed(borrowed) => {
+                mem::size_of_val(self) + size_of_borrowed(*borrowed, tracker)
+            }
+            Cow::Owned(owned) => mem::size_of_val(self) + size_of_heap_of_value(owned, tracker),
         }
     }
+}
+
+#[cfg(test)]
+mod test_string_types {
+    use super::*;
 
     #[test]
-    fn test_ints() {
-        assert_eq!(MemoryUsage::size_of_val(&32, &mut BTreeSet::new()), 4);
-        assert_eq!(32.size_of_val(&mut BTreeSet::new()), 4);
+    fn test_str() {
+        assert_size_of_val_eq!(*"hello", 5);
+        assert_size_of_val_eq!("hello", POINTER_BYTE_SIZE * 2 + 5);
     }
 
     #[test]
-    fn test_arrays() {
-        let x: [[u8; 7]; 13] = [[0; 7]; 13];
-        assert_eq!(7 * 13, mem::size_of_val(&x));
-        assert_eq!(7 * 13, MemoryUsage::size_of_val(&x, &mut BTreeSet::new()));
+    fn test_string() {
+        let mut string = String::with_capacity(32);
+        string.push_str("hello");
+
+        assert_size_of_val_eq!(string, POINTER_BYTE_SIZE * 3 + 32);
+        assert_size_of_val_eq!(String::new(), POINTER_BYTE_SIZE * 3);
     }
 
     #[test]
-    fn test_slice_no_static_size() {
-        {
-            let x: [u8; 13] = [0; 13];
-            let y: &[u8] = &x;
-            assert_eq!(13, mem::size_of_val(y));
-            assert_eq!(13, MemoryUsage::size_of_val(y, &mut BTreeSet::new()));
-        }
+    fn test_boxed_str() {
+        let boxed: Box<str> = "hello".into();
 
-        {
-            let mut x: [TestMemoryUsage; 13] = [TestMemoryUsage {
-                size_to_report: mem::size_of::<TestMemoryUsage>(),
-            }; 13];
-            x[0].size_to_report += 7;
-            let y: &[TestMemoryUsage] = &x;
-            assert_eq!(13 * mem::size_of::<TestMemoryUsage>(), mem::size_of_val(y));
-            assert_eq!(
-                13 * mem::size_of::<TestMemoryUsage>() + 7,
-                MemoryUsage::size_of_val(y, &mut BTreeSet::new())
-            );
-        }
+        assert_size_of_val_eq!(boxed, POINTER_BYTE_SIZE * 2 + 5);
     }
 
     #[test]
-    fn test_vecs() {
-        let mut x = vec![];
-        let empty_vec_size = mem::size_of_val(&x);
-        let tmu_size = mem::size_of::<TestMemoryUsage>();
-        x.push(TestMemoryUsage {
-            size_to_report: tmu_size + 3,
-        });
-        x.push(TestMemoryUsage {
-            size_to_report: tmu_size + 7,
-        });
-        assert_eq!(empty_vec_size, mem::size_of_val(&x));
-        assert_eq!(
-            empty_vec_size + 2 * tmu_size + 3 + 7,
-            MemoryUsage::size_of_val(&x, &mut BTreeSet::new())
-        );
+    fn test_cow_str() {
+        let borrowed: Cow<str> = Cow::Borrowed("hello");
+        let mut owned: Cow<str> = Cow::Owned(String::with_capacity(32));
+        owned.to_mut().push_str("hello");
+
+        assert_size_of_val_eq!(borrowed, mem::size_of::<Cow<str>>() + 5);
+        assert_size_of_val_eq!(owned, mem::size_of::<Cow<str>>() + 32);
     }
 
     #[test]
-    fn test_double_counting() {
+    fn test_c_string() {
+        let c_string = CString::new("hello").unwrap();
+
+        assert_size_of_val_eq!(c_string, POINTER_BYTE_SIZE * 2 + 6);
+        assert_size_of_val_eq!(*c_string.as_c_str(), 6);
+    }
+
+    #[test]
+    fn test_os_string() {
+        let mut os_string = OsString::with_capacity(32);
+        os_string.push("hello");
+
+        assert_size_of_val_eq!(os_string, POINTER_BYTE_SIZE * 3 + 32);
+        assert_size_of_val_eq!(*os_string.as_os_str(), 5);
+    }
+
+    #[test]
+    fn test_path_buf() {
+        let mut path_buf = PathBuf::with_capacity(32);
+        path_buf.push("hello");
+
+        assert_size_of_val_eq!(path_buf, POINTER_BYTE_SIZE * 3 + 32);
+        assert_size_of_val_eq!(*path_buf.as_path(), 5);
+    }
+
+    #[test]
+    fn test_string_unused_capacity() {
+        let mut string = String::with_capacity(32);
+        string.push_str("hello");
+
+        let mut tracker = UnusedCapacityTracker::default();
+        string.size_of_val(&mut tracker);
+        assert_eq!(27, tracker.unused);
+    }
+}
+
+// tuples
+macro_rules! impl_memory_usage_for_tuple {
+    ( $( $name:ident ),+ ) => {
+        impl< $( $name: MemoryUsage ),+ > MemoryUsage for ( $( $name, )+ ) {
+            #[allow(non_snake_case)]
+            fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+                let ( $( $name, )+ ) = self;
+
+                mem::size_of_val(self)
+                    $( + size_of_heap_of_value($name, tracker) )+
+            }
+        }
+    };
+}
+
+impl_memory_usage_for_tuple!(A);
+impl_memory_usage_for_tuple!(A, B);
+impl_memory_usage_for_tuple!(A, B, C);
+impl_memory_usage_for_tuple!(A, B, C, D);
+impl_memory_usage_for_tuple!(A, B, C, D, E);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G, H);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G, H, I);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G, H, I, J);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G, H, I, J, K);
+impl_memory_usage_for_tuple!(A, B, C, D, E, F, G, H, I, J, K, L);
+
+// Standard library types
+
+// Types that own nothing are measured like primitive types.
+impl_memory_usage_for_primitive!(
+    (),
+    cmp::Ordering,
+    Duration,
+    Instant,
+    SystemTime,
+    RangeFull,
+    PhantomPinned,
+    IpAddr,
+    Ipv4Addr,
+    Ipv6Addr,
+    SocketAddr,
+    SocketAddrV4,
+    SocketAddrV6,
+    NonZeroI8,
+    NonZeroI16,
+    NonZeroI32,
+    NonZeroI64,
+    NonZeroI128,
+    NonZeroIsize,
+    NonZeroU8,
+    NonZeroU16,
+    NonZeroU32,
+    NonZeroU64,
+    NonZeroU128,
+    NonZeroUsize,
+    AtomicBool,
+    AtomicI8,
+    AtomicI16,
+    AtomicI32,
+    AtomicIsize,
+    AtomicU8,
+    AtomicU16,
+    AtomicU32,
+    AtomicUsize
+);
+
+#[cfg(target_has_atomic = "64")]
+impl_memory_usage_for_primitive!(AtomicI64, AtomicU64);
+
+// Same as pointers, the pointee isn't necessarily safe to dereference.
+impl<T> MemoryUsage for AtomicPtr<T> {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+// Wrappers have the same layout as the value they wrap.
+impl<T: MemoryUsage> MemoryUsage for Wrapping<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        MemoryUsage::size_of_val(&self.0, tracker)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for cmp::Reverse<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        MemoryUsage::size_of_val(&self.0, tracker)
+    }
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for ManuallyDrop<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        MemoryUsage::size_of_val(&**self, tracker)
+    }
+}
+
+// Ranges
+impl<T: MemoryUsage> MemoryUsage for Range<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + size_of_heap_of_elements([&self.start, &self.end].iter().copied(), tracker)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for RangeInclusive<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + size_of_heap_of_elements([self.start(), self.end()].iter().copied(), tracker)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for RangeFrom<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(std::iter::once(&self.start), tracker)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for RangeTo<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(std::iter::once(&self.end), tracker)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for RangeToInclusive<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(std::iter::once(&self.end), tracker)
+    }
+}
+
+#[cfg(test)]
+mod test_standard_types {
+    use super::*;
+
+    #[test]
+    fn test_tuples() {
+        assert_size_of_val_eq!((1u8,), 1);
+        assert_size_of_val_eq!((1u8, 2u32), 8);
+        assert_size_of_val_eq!(
+            (1u8, vec![1u8; 16], String::from("hello")),
+            mem::size_of::<(u8, Vec<u8>, String)>() + 16 + 5
+        );
+        assert_size_of_val_eq!(
+            (1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8),
+            12
+        );
+    }
+
+    #[test]
+    fn test_option() {
+        let size = mem::size_of::<Option<u64>>();
+
+        assert_size_of_val_eq!(None::<u64>, size);
+        assert_size_of_val_eq!(Some(1u64), size);
+        assert_size_of_val_eq!(Some(vec![1u8; 16]), mem::size_of::<Vec<u8>>() + 16);
+    }
+
+    #[test]
+    fn test_result() {
+        let ok: Result<Vec<u8>, String> = Ok(vec![1u8; 16]);
+        let err: Result<Vec<u8>, String> = Err(String::from("hello"));
+
+        assert_size_of_val_eq!(ok, mem::size_of::<Result<Vec<u8>, String>>() + 16);
+        assert_size_of_val_eq!(err, mem::size_of::<Result<Vec<u8>, String>>() + 5);
+    }
+
+    #[test]
+    fn test_plain_types() {
+        assert_size_of_val_eq!((), 0);
+        assert_size_of_val_eq!(cmp::Ordering::Less, 1);
+        assert_size_of_val_eq!(Duration::from_secs(1), mem::size_of::<Duration>());
+        assert_size_of_val_eq!(Instant::now(), mem::size_of::<Instant>());
+        assert_size_of_val_eq!(SystemTime::now(), mem::size_of::<SystemTime>());
+        assert_size_of_val_eq!(NonZeroU32::new(1).unwrap(), 4);
+        assert_size_of_val_eq!(AtomicUsize::new(1), POINTER_BYTE_SIZE);
+        assert_size_of_val_eq!(AtomicPtr::new(&mut 1u8), POINTER_BYTE_SIZE);
+        assert_size_of_val_eq!(IpAddr::from([127, 0, 0, 1]), mem::size_of::<IpAddr>());
+        assert_size_of_val_eq!(
+            SocketAddr::from(([127, 0, 0, 1], 80)),
+            mem::size_of::<SocketAddr>()
+        );
+        assert_size_of_val_eq!(PhantomPinned, 0);
+    }
+
+    #[test]
+    fn test_wrappers() {
+        assert_size_of_val_eq!(Wrapping(1u32), 4);
+        assert_size_of_val_eq!(
+            cmp::Reverse(String::from("hello")),
+            mem::size_of::<String>() + 5
+        );
+        assert_size_of_val_eq!(
+            ManuallyDrop::new(vec![1u8; 16]),
+            mem::size_of::<Vec<u8>>() + 16
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_size_of_val_eq!(1u32..2, 8);
+        assert_size_of_val_eq!(1u32..=2, mem::size_of::<RangeInclusive<u32>>());
+        assert_size_of_val_eq!(1u32.., 4);
+        assert_size_of_val_eq!(..2u32, 4);
+        assert_size_of_val_eq!(..=2u32, 4);
+        assert_size_of_val_eq!(.., 0);
+        assert_size_of_val_eq!(
+            String::from("a")..String::from("abc"),
+            2 * mem::size_of::<String>() + 1 + 3
+        );
+    }
+}
+
+/// Returns the size charged to one owner of a shared allocation, according
+/// to the tracker's [`SharedPolicy`].
+///
+/// `value` lives in the shared allocation, after a refcount header of two
+/// `usize` (`strong` and `weak`). `pointer_size` is the size of the shared
+/// pointer itself, and `type_name` the name of its type.
+fn size_of_shared<T: MemoryUsage + ?Sized>(
+    type_name: &'static str,
+    pointer_size: usize,
+    value: &T,
+    strong_count: usize,
+    tracker: &mut dyn MemoryUsageTracker,
+) -> usize {
+    let address = value as *const T as *const ();
+    tracker.shared_reference(address);
+
+    let size = if tracker.track_value(address, mem::size_of_val(value)) {
+        // The start of the allocation depends on the private layout of
+        // `Rc` and `Arc`, so the allocator can't be asked for its usable
+        // size: it is always the size of the layout.
+        let (layout, _) = Layout::new::<[usize; 2]>()
+            .extend(Layout::for_value(value))
+            .expect("a shared allocation has a valid layout");
+        let allocation_size = layout.pad_to_align().size();
+        let size = size_of_heap_allocation(
+            Some(address),
+            type_name,
+            allocation_size,
+            tracker,
+            |tracker| size_of_heap_of_value(value, tracker),
+        );
+
+        tracker.shared_allocation(address, size);
+
+        Some(size)
+    } else {
+        None
+    };
+
+    let charged = match tracker.shared_policy() {
+        SharedPolicy::FirstOwner => size.unwrap_or(0),
+        SharedPolicy::EvenSplit => {
+            size.or_else(|| tracker.size_of_shared_allocation(address))
+                .unwrap_or(0)
+                / strong_count.max(1)
+        }
+        SharedPolicy::Unowned => 0,
+    };
+    tracker.exit_shared_reference(charged);
+
+    pointer_size + charged
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for Arc<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        size_of_shared(
+            any::type_name::<Self>(),
+            mem::size_of_val(self),
+            self.as_ref(),
+            Arc::strong_count(self),
+            tracker,
+        )
+    }
+}
+
+// A `Weak` doesn't own its value, only the pointer is counted.
+impl<T: ?Sized> MemoryUsage for sync::Weak<T> {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+// A `Box` is the unique owner of its pointee, so the pointee is always
+// counted and never goes through `MemoryUsageTracker::track`.
+//
+// `T` can be unsized, which covers `Box<str>`, `Box<[T]>` and boxed
+// trait objects. A trait object is supported when its trait has
+// `MemoryUsage` as a supertrait, e.g. `Box<dyn Plugin>` with
+// `trait Plugin: MemoryUsage`.
+impl<T: MemoryUsage + ?Sized> MemoryUsage for Box<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        let pointee = self.as_ref();
+        let bytes = size_of_block(
+            pointee as *const T as *const u8,
+            Layout::for_value(pointee),
+            tracker,
+        );
+
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                Some(pointee as *const T as *const ()),
+                any::type_name::<Self>(),
+                bytes,
+                tracker,
+                |tracker| size_of_heap_of_value(pointee, tracker),
+            )
+    }
+}
+
+#[cfg(test)]
+mod test_box_types {
+    use super::*;
+
+    #[test]
+    fn test_box() {
+        assert_size_of_val_eq!(Box::new(1u64), POINTER_BYTE_SIZE + 8);
+        assert_size_of_val_eq!(Box::new(std::marker::PhantomData::<u64>), POINTER_BYTE_SIZE);
+        assert_size_of_val_eq!(
+            Box::new(Box::new(1u64)),
+            POINTER_BYTE_SIZE + POINTER_BYTE_SIZE + 8
+        );
+    }
+
+    #[test]
+    fn test_boxed_slice() {
+        let boxed: Box<[u32]> = vec![1, 2, 3].into_boxed_slice();
+
+        assert_size_of_val_eq!(boxed, POINTER_BYTE_SIZE * 2 + 3 * 4);
+    }
+
+    #[test]
+    fn test_box_is_not_deduplicated() {
+        let boxed = Box::new(1u64);
+        let mut tracker = BTreeSet::new();
+
+        assert_eq!(POINTER_BYTE_SIZE + 8, boxed.size_of_val(&mut tracker));
+        assert_eq!(POINTER_BYTE_SIZE + 8, boxed.size_of_val(&mut tracker));
+    }
+
+    #[test]
+    fn test_boxed_trait_object() {
+        trait Plugin: MemoryUsage {}
+
+        struct Small(u8);
+
+        impl MemoryUsage for Small {
+            fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+                self.0.size_of_val(tracker)
+            }
+        }
+
+        impl Plugin for Small {}
+
+        struct Large(Vec<u64>);
+
+        impl MemoryUsage for Large {
+            fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+                mem::size_of_val(self) + self.0.size_of_val(tracker) - mem::size_of_val(&self.0)
+            }
+        }
+
+        impl Plugin for Large {}
+
+        let plugins: Vec<Box<dyn Plugin>> =
+            vec![Box::new(Small(1)), Box::new(Large(Vec::with_capacity(4)))];
+
+        assert_size_of_val_eq!(
+            plugins,
+            mem::size_of::<Vec<Box<dyn Plugin>>>()
+                + 2 * mem::size_of::<Box<dyn Plugin>>()
+                + 1
+                + mem::size_of::<Vec<u64>>()
+                + 4 * 8
+        );
+    }
+}
+
+// Cells
+//
+// A `Cell` can only be read by copying its value.
+impl<T: MemoryUsage + Copy> MemoryUsage for Cell<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        MemoryUsage::size_of_val(&self.get(), tracker)
+    }
+}
+
+// The value of an `UnsafeCell` may be mutated concurrently, it cannot be
+// read safely. Only its inline size is counted.
+impl<T: ?Sized> MemoryUsage for UnsafeCell<T> {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for OnceCell<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + self
+                .get()
+                .map(|value| size_of_heap_of_value(value, tracker))
+                .unwrap_or(0)
+    }
+}
+
+// A `RefCell` that is mutably borrowed is borrowed by the current thread,
+// so its value is always skipped, whatever the `LockPolicy`.
+impl<T: MemoryUsage + ?Sized> MemoryUsage for RefCell<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + match self.try_borrow() {
+                Ok(value) => size_of_heap_of_value(&*value, tracker),
+                Err(_) => {
+                    tracker.record_unmeasured(any::type_name::<Self>());
+
+                    0
+                }
+            }
+    }
+}
+
+// `Ref` and `RefMut` are references.
+impl<T: MemoryUsage + ?Sized> MemoryUsage for Ref<'_, T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_borrowed(&**self, tracker)
+    }
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for RefMut<'_, T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_borrowed(&**self, tracker)
+    }
+}
+
+// Is a Pin always dereferenceable?
+//impl<T: MemoryUsage> MemoryUsage for Pin<T> {
+//}
+
+// Locks
+
+/// The first and the longest sleeps between two attempts to acquire a lock
+/// with the [`LockPolicy::Block`] policy.
+const LOCK_MIN_BACKOFF: Duration = Duration::from_micros(50);
+const LOCK_MAX_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Acquires a lock with `try_lock` according to the tracker's
+/// [`LockPolicy`]. Returns `None` if the lock is still held when the
+/// policy gives up.
+///
+/// A poisoned lock is acquired as usual.
+fn lock_with_policy<G>(
+    tracker: &dyn MemoryUsageTracker,
+    mut try_lock: impl FnMut() -> TryLockResult<G>,
+) -> Option<G> {
+    let mut try_lock = || match try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(error)) => Some(error.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    };
+
+    if let Some(guard) = try_lock() {
+        return Some(guard);
+    }
+
+    // Never call a blocking `lock`: it deadlocks if the lock is held by the
+    // current thread. Sleep between the attempts rather than spinning, with
+    // an exponential backoff.
+    if let LockPolicy::Block(timeout) = tracker.lock_policy() {
+        // A timeout too large for an `Instant`, like `Duration::MAX`, means
+        // waiting forever.
+        let deadline = Instant::now().checked_add(timeout);
+        let mut backoff = LOCK_MIN_BACKOFF;
+
+        loop {
+            let sleep = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+
+                    backoff.min(deadline - now)
+                }
+                None => backoff,
+            };
+
+            thread::sleep(sleep);
+            backoff = (backoff * 2).min(LOCK_MAX_BACKOFF);
+
+            if let Some(guard) = try_lock() {
+                return Some(guard);
+            }
+        }
+    }
+
+    None
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for Mutex<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + match lock_with_policy(tracker, || self.try_lock()) {
+                Some(value) => size_of_heap_of_value(&*value, tracker),
+                None => {
+                    tracker.record_unmeasured(any::type_name::<Self>());
+
+                    0
+                }
+            }
+    }
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for RwLock<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + match lock_with_policy(tracker, || self.try_read()) {
+                Some(value) => size_of_heap_of_value(&*value, tracker),
+                None => {
+                    tracker.record_unmeasured(any::type_name::<Self>());
+
+                    0
+                }
+            }
+    }
+}
+
+// A `OnceLock` that is being initialized has no value yet.
+impl<T: MemoryUsage> MemoryUsage for OnceLock<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + self
+                .get()
+                .map(|value| size_of_heap_of_value(value, tracker))
+                .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test_interior_mutability_types {
+    use super::*;
+    use crate::{MeasureError, Tracker};
+    use std::sync::mpsc;
+
+    #[test]
+    fn test_cell() {
+        assert_size_of_val_eq!(Cell::new(1u64), 8);
+    }
+
+    #[test]
+    fn test_unsafe_cell() {
+        assert_size_of_val_eq!(UnsafeCell::new(vec![1u8; 16]), mem::size_of::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_once_cell() {
+        let cell = OnceCell::new();
+        assert_size_of_val_eq!(cell, mem::size_of::<OnceCell<Vec<u8>>>());
+
+        cell.set(vec![1u8; 16]).unwrap();
+        assert_size_of_val_eq!(cell, mem::size_of::<OnceCell<Vec<u8>>>() + 16);
+    }
+
+    #[test]
+    fn test_once_lock() {
+        let lock = OnceLock::new();
+        assert_size_of_val_eq!(lock, mem::size_of::<OnceLock<Vec<u8>>>());
+
+        lock.set(vec![1u8; 16]).unwrap();
+        assert_size_of_val_eq!(lock, mem::size_of::<OnceLock<Vec<u8>>>() + 16);
+    }
+
+    #[test]
+    fn test_ref_cell() {
+        let cell = RefCell::new(vec![1u8; 16]);
+        let size = mem::size_of_val(&cell);
+
+        assert_size_of_val_eq!(cell, size + 16);
+
+        {
+            let _shared = cell.borrow();
+            assert_size_of_val_eq!(cell, size + 16);
+        }
+
+        {
+            let _exclusive = cell.borrow_mut();
+            let mut tracker = Tracker::new();
+
+            assert_eq!(size, cell.size_of_val(&mut tracker));
+            assert_eq!(
+                &[any::type_name::<RefCell<Vec<u8>>>()],
+                tracker.unmeasured_values()
+            );
+        }
+    }
+
+    #[test]
+    fn test_mutex() {
+        let mutex = Mutex::new(vec![1u8; 16]);
+        let size = mem::size_of_val(&mutex);
+
+        assert_size_of_val_eq!(mutex, size + 16);
+
+        let _guard = mutex.lock().unwrap();
+        let mut tracker = Tracker::new();
+
+        assert_eq!(size, mutex.size_of_val(&mut tracker));
+        assert_eq!(
+            &[any::type_name::<Mutex<Vec<u8>>>()],
+            tracker.unmeasured_values()
+        );
+    }
+
+    #[test]
+    fn test_poisoned_mutex() {
+        let mutex = Arc::new(Mutex::new(vec![1u8; 16]));
+        let size = mem::size_of::<Mutex<Vec<u8>>>();
+
+        {
+            let mutex = mutex.clone();
+            thread::spawn(move || {
+                let _guard = mutex.lock().unwrap();
+                panic!("poison the mutex");
+            })
+            .join()
+            .unwrap_err();
+        }
+
+        assert!(mutex.is_poisoned());
+        assert_size_of_val_eq!(*mutex, size + 16);
+    }
+
+    #[test]
+    fn test_rw_lock() {
+        let lock = RwLock::new(vec![1u8; 16]);
+        let size = mem::size_of_val(&lock);
+
+        {
+            let _read = lock.read().unwrap();
+            assert_size_of_val_eq!(lock, size + 16);
+        }
+
+        {
+            let _write = lock.write().unwrap();
+            let mut tracker = Tracker::new();
+
+            assert_eq!(size, lock.size_of_val(&mut tracker));
+            assert_eq!(1, tracker.unmeasured_values().len());
+        }
+    }
+
+    #[test]
+    fn test_lock_policy_block_does_not_deadlock() {
+        let lock = RwLock::new(vec![1u8; 16]);
+        let size = mem::size_of_val(&lock);
+        let _write = lock.write().unwrap();
+        let mut tracker =
+            Tracker::new().with_lock_policy(LockPolicy::Block(Duration::from_millis(10)));
+
+        assert_eq!(size, lock.size_of_val(&mut tracker));
+        assert_eq!(1, tracker.unmeasured_values().len());
+    }
+
+    #[test]
+    fn test_lock_policy_block_forever() {
+        let mutex = Arc::new(Mutex::new(vec![1u8; 16]));
+        let size = mem::size_of::<Mutex<Vec<u8>>>() + 16;
+        let mut tracker = Tracker::new().with_lock_policy(LockPolicy::Block(Duration::MAX));
+
+        assert_eq!(size, MemoryUsage::size_of_val(&*mutex, &mut tracker));
+
+        // The deadline is never computed when the lock is free, hold it
+        // for a moment.
+        let (locked_sender, locked_receiver) = mpsc::channel();
+        let holder = {
+            let mutex = mutex.clone();
+
+            thread::spawn(move || {
+                let _guard = mutex.lock().unwrap();
+                locked_sender.send(()).unwrap();
+                thread::sleep(Duration::from_millis(20));
+            })
+        };
+
+        locked_receiver.recv().unwrap();
+
+        assert_eq!(size, MemoryUsage::size_of_val(&*mutex, &mut tracker));
+        assert!(tracker.unmeasured_values().is_empty());
+
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_policy_block() {
+        let mutex = Arc::new(Mutex::new(vec![1u8; 16]));
+        let size = mem::size_of::<Mutex<Vec<u8>>>();
+        let (locked_sender, locked_receiver) = mpsc::channel();
+
+        let holder = {
+            let mutex = mutex.clone();
+
+            thread::spawn(move || {
+                let _guard = mutex.lock().unwrap();
+                locked_sender.send(()).unwrap();
+                thread::sleep(Duration::from_millis(50));
+            })
+        };
+
+        locked_receiver.recv().unwrap();
+
+        let mut tracker =
+            Tracker::new().with_lock_policy(LockPolicy::Block(Duration::from_secs(60)));
+
+        assert_eq!(size + 16, MemoryUsage::size_of_val(&*mutex, &mut tracker));
+        assert!(tracker.unmeasured_values().is_empty());
+
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_policy_fail() {
+        let mutex = Mutex::new(vec![1u8; 16]);
+        let mut tracker = Tracker::new().with_lock_policy(LockPolicy::Fail);
+
+        assert_eq!(Ok(mem::size_of_val(&mutex) + 16), tracker.measure(&mutex));
+
+        let _guard = mutex.lock().unwrap();
+        let mut tracker = Tracker::new().with_lock_policy(LockPolicy::Fail);
+
+        assert_eq!(
+            Err(MeasureError::Unmeasured {
+                type_name: any::type_name::<Mutex<Vec<u8>>>()
+            }),
+            tracker.measure(&mutex)
+        );
+
+        // The other entry points only record the value.
+        let mut tracker = Tracker::new().with_lock_policy(LockPolicy::Fail);
+
+        assert_eq!(
+            mem::size_of_val(&mutex),
+            tracker.report(&mutex).total_bytes()
+        );
+        assert_eq!(
+            [any::type_name::<Mutex<Vec<u8>>>()],
+            tracker.unmeasured_values()
+        );
+    }
+}
+
+// TODO: NonNull might be possible when '*const T' is MemoryUsage.
+
+impl<T: MemoryUsage> MemoryUsage for Option<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self) + size_of_heap_of_elements(self.iter(), tracker)
+    }
+}
+
+impl<T: MemoryUsage + ?Sized> MemoryUsage for Rc<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        size_of_shared(
+            any::type_name::<Self>(),
+            mem::size_of_val(self),
+            self.as_ref(),
+            Rc::strong_count(self),
+            tracker,
+        )
+    }
+}
+
+// A `Weak` doesn't own its value, only the pointer is counted.
+impl<T: ?Sized> MemoryUsage for rc::Weak<T> {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
+#[cfg(test)]
+mod test_shared_types {
+    use super::*;
+    use crate::Tracker;
+
+    const HEADER_SIZE: usize = 2 * POINTER_BYTE_SIZE;
+
+    #[test]
+    fn test_rc() {
+        let rc = Rc::new(1u64);
+
+        assert_size_of_val_eq!(rc, POINTER_BYTE_SIZE + HEADER_SIZE + 8);
+    }
+
+    #[test]
+    fn test_rc_header_padding() {
+        #[repr(align(32))]
+        struct Aligned(u8);
+
+        impl MemoryUsage for Aligned {
+            fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+                mem::size_of_val(self) + self.0.size_of_val(tracker) - 1
+            }
+        }
+
+        assert_size_of_val_eq!(Rc::new(Aligned(1)), POINTER_BYTE_SIZE + 32 + 32);
+    }
+
+    #[test]
+    fn test_arc() {
+        let arc = Arc::new(vec![1u8; 16]);
+
+        assert_size_of_val_eq!(
+            arc,
+            POINTER_BYTE_SIZE + HEADER_SIZE + mem::size_of::<Vec<u8>>() + 16
+        );
+    }
+
+    #[test]
+    fn test_rc_of_unsized_value() {
+        let rc: Rc<str> = Rc::from("hello");
+
+        // The allocation is padded to its alignment.
+        assert_size_of_val_eq!(rc, POINTER_BYTE_SIZE * 2 + HEADER_SIZE + 8);
+    }
+
+    #[test]
+    fn test_weak() {
+        let rc = Rc::new(1u64);
+        let arc = Arc::new(1u64);
+
+        assert_size_of_val_eq!(Rc::downgrade(&rc), POINTER_BYTE_SIZE);
+        assert_size_of_val_eq!(Arc::downgrade(&arc), POINTER_BYTE_SIZE);
+    }
+
+    #[test]
+    fn test_shared_policy_first_owner() {
+        let rc = Rc::new(1u64);
+        let value = vec![rc.clone(), rc.clone(), rc];
+        let mut tracker = Tracker::new().with_shared_policy(SharedPolicy::FirstOwner);
+
+        assert_eq!(
+            mem::size_of_val(&value) + 3 * POINTER_BYTE_SIZE + HEADER_SIZE + 8,
+            value.size_of_val(&mut tracker)
+        );
+        assert_eq!(HEADER_SIZE + 8, tracker.shared_bytes());
+    }
+
+    #[test]
+    fn test_shared_policy_even_split() {
+        let arc = Arc::new([0u64; 4]);
+        let _other_owner = arc.clone();
+        let value = vec![arc.clone(), arc];
+        let mut tracker = Tracker::new().with_shared_policy(SharedPolicy::EvenSplit);
+
+        // 2 of the 3 strong owners are visited.
+        assert_eq!(
+            mem::size_of_val(&value) + 2 * POINTER_BYTE_SIZE + 2 * (HEADER_SIZE + 32) / 3,
+            value.size_of_val(&mut tracker)
+        );
+        assert_eq!(HEADER_SIZE + 32, tracker.shared_bytes());
+    }
+
+    #[test]
+    fn test_shared_policy_even_split_without_memory() {
+        // This tracker doesn't remember the size of the shared
+        // allocations, so only the first owner is charged its share.
+        struct EvenSplit(BTreeSet<*const ()>);
+
+        impl MemoryUsageTracker for EvenSplit {
+            fn track(&mut self, address: *const ()) -> bool {
+                self.0.track(address)
+            }
+
+            fn shared_policy(&self) -> SharedPolicy {
+                SharedPolicy::EvenSplit
+            }
+        }
+
+        let rc = Rc::new(1u64);
+        let value = vec![rc.clone(), rc];
+
+        assert_eq!(
+            mem::size_of_val(&value) + 2 * POINTER_BYTE_SIZE + (HEADER_SIZE + 8) / 2,
+            value.size_of_val(&mut EvenSplit(BTreeSet::new()))
+        );
+    }
+
+    #[test]
+    fn test_shared_policy_unowned() {
+        let rc = Rc::new(1u64);
+        let value = vec![rc.clone(), rc];
+        let mut tracker = Tracker::new().with_shared_policy(SharedPolicy::Unowned);
+
+        assert_eq!(
+            mem::size_of_val(&value) + 2 * POINTER_BYTE_SIZE,
+            value.size_of_val(&mut tracker)
+        );
+        assert_eq!(HEADER_SIZE + 8, tracker.shared_bytes());
+    }
+
+    #[test]
+    fn test_nested_shared_allocations() {
+        let inner = Rc::new(1u64);
+        let outer = Rc::new(vec![inner.clone(), inner]);
+        let value = vec![outer.clone(), outer];
+
+        assert_size_of_val_eq!(
+            value,
+            mem::size_of::<Vec<Rc<Vec<Rc<u64>>>>>()
+                + 2 * POINTER_BYTE_SIZE
+                + HEADER_SIZE
+                + mem::size_of::<Vec<Rc<u64>>>()
+                + 2 * POINTER_BYTE_SIZE
+                + HEADER_SIZE
+                + 8
+        );
+    }
+}
+
+impl<T: MemoryUsage, E: MemoryUsage> MemoryUsage for Result<T, E> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        mem::size_of_val(self)
+            + match self {
+                Ok(value) => size_of_heap_of_value(value, tracker),
+                Err(error) => size_of_heap_of_value(error, tracker),
+            }
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for Vec<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // The heap buffer is `capacity` elements long, whether they are
+        // initialized or not. The elements are stored inline in the
+        // buffer, so only what they own outside of it is added.
+        tracker.unused_capacity((self.capacity() - self.len()) * mem::size_of::<T>());
+        let bytes = size_of_block(
+            self.as_ptr() as *const u8,
+            Layout::array::<T>(self.capacity()).expect("a `Vec` has a valid layout"),
+            tracker,
+        );
+
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                Some(self.as_ptr() as *const ()),
+                any::type_name::<Self>(),
+                bytes,
+                tracker,
+                |tracker| size_of_heap_of_elements(self.iter(), tracker),
+            )
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for VecDeque<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // Same as `Vec`, the ring buffer is `capacity` elements long. Its
+        // address is only known through its elements, so its size is
+        // never asked to the allocator.
+        tracker.unused_capacity((self.capacity() - self.len()) * mem::size_of::<T>());
+
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                self.front().map(|front| front as *const T as *const ()),
+                any::type_name::<Self>(),
+                self.capacity() * mem::size_of::<T>(),
+                tracker,
+                |tracker| size_of_heap_of_elements(self.iter(), tracker),
+            )
+    }
+}
+
+impl<T: MemoryUsage> MemoryUsage for BinaryHeap<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // A `BinaryHeap` is a `Vec`.
+        tracker.unused_capacity((self.capacity() - self.len()) * mem::size_of::<T>());
+        let bytes = size_of_block(
+            self.as_slice().as_ptr() as *const u8,
+            Layout::array::<T>(self.capacity()).expect("a `BinaryHeap` has a valid layout"),
+            tracker,
+        );
+
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                Some(self.as_slice().as_ptr() as *const ()),
+                any::type_name::<Self>(),
+                bytes,
+                tracker,
+                |tracker| size_of_heap_of_elements(self.iter(), tracker),
+            )
+    }
+}
+
+/// Mirror of the node of a `LinkedList`.
+#[allow(dead_code)]
+struct LinkedListNode<T> {
+    next: Option<NonNull<()>>,
+    prev: Option<NonNull<()>>,
+    element: T,
+}
+
+impl<T: MemoryUsage> MemoryUsage for LinkedList<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // Every element is allocated in its own node, next to the links.
+        mem::size_of_val(self)
+            + self
+                .iter()
+                .map(|element| {
+                    size_of_heap_allocation(
+                        Some(element as *const T as *const ()),
+                        any::type_name::<Self>(),
+                        mem::size_of::<LinkedListNode<T>>(),
+                        tracker,
+                        |tracker| size_of_heap_of_elements(std::iter::once(element), tracker),
+                    )
+                })
+                .sum::<usize>()
+    }
+}
+
+/// Width of a group of control bytes in the hash tables of
+/// `std::collections`, i.e. the SIMD width used to probe them.
+const HASH_TABLE_GROUP_WIDTH: usize = if cfg!(all(
+    any(target_arch = "x86", target_arch = "x86_64"),
+    target_feature = "sse2"
+)) {
+    16
+} else if cfg!(all(
+    target_arch = "aarch64",
+    target_feature = "neon",
+    target_endian = "little"
+)) {
+    8
+} else {
+    mem::size_of::<usize>()
+};
+
+/// Returns the estimated number of buckets of a hash table that can hold
+/// `capacity` elements.
+///
+/// A hash table keeps 1/8th of its buckets empty, except when it has
+/// less than 8 buckets.
+fn hash_table_buckets(capacity: usize) -> usize {
+    match capacity {
+        0 => 0,
+        1..=7 => (capacity + 1).next_power_of_two(),
+        _ => (capacity / 7 * 8).next_power_of_two(),
+    }
+}
+
+/// Returns the estimated size of the allocation of a hash table storing
+/// elements of type `T`, which can hold `capacity` elements.
+///
+/// The allocation contains one slot of type `T` per bucket, followed by one
+/// control byte per bucket plus an extra group of control bytes.
+fn size_of_hash_table<T>(capacity: usize) -> usize {
+    let buckets = hash_table_buckets(capacity);
+
+    if buckets == 0 {
+        return 0;
+    }
+
+    let control_alignment = mem::align_of::<T>().max(HASH_TABLE_GROUP_WIDTH);
+    let control_offset = (buckets * mem::size_of::<T>()).next_multiple_of(control_alignment);
+
+    control_offset + buckets + HASH_TABLE_GROUP_WIDTH
+}
+
+impl<K: MemoryUsage, V: MemoryUsage, S> MemoryUsage for HashMap<K, V, S> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        tracker.unused_capacity(
+            (hash_table_buckets(self.capacity()) - self.len()) * mem::size_of::<(K, V)>(),
+        );
+
+        // The address of the table is only known through its elements.
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                self.keys().next().map(|key| key as *const K as *const ()),
+                any::type_name::<Self>(),
+                size_of_hash_table::<(K, V)>(self.capacity()),
+                tracker,
+                |tracker| {
+                    size_of_heap_of_elements(self.keys(), tracker)
+                        + size_of_heap_of_elements(self.values(), tracker)
+                },
+            )
+    }
+}
+
+impl<T: MemoryUsage, S> MemoryUsage for HashSet<T, S> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        tracker.unused_capacity(
+            (hash_table_buckets(self.capacity()) - self.len()) * mem::size_of::<T>(),
+        );
+
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                self.iter()
+                    .next()
+                    .map(|value| value as *const T as *const ()),
+                any::type_name::<Self>(),
+                size_of_hash_table::<T>(self.capacity()),
+                tracker,
+                |tracker| size_of_heap_of_elements(self.iter(), tracker),
+            )
+    }
+}
+
+/// Maximum number of elements in a node of a `BTreeMap`.
+const BTREE_NODE_CAPACITY: usize = 11;
+
+/// Mirror of a leaf node of a `BTreeMap`.
+#[allow(dead_code)]
+struct BTreeLeafNode<K, V> {
+    parent: Option<NonNull<()>>,
+    parent_index: MaybeUninit<u16>,
+    len: u16,
+    keys: [MaybeUninit<K>; BTREE_NODE_CAPACITY],
+    values: [MaybeUninit<V>; BTREE_NODE_CAPACITY],
+}
+
+/// Mirror of an internal node of a `BTreeMap`.
+#[allow(dead_code)]
+struct BTreeInternalNode<K, V> {
+    data: BTreeLeafNode<K, V>,
+    edges: [MaybeUninit<NonNull<()>>; BTREE_NODE_CAPACITY + 1],
+}
+
+/// Returns the estimated size of the nodes of a B-tree holding `len`
+/// elements.
+///
+/// The shape of a B-tree depends on the order of the insertions and
+/// removals, which is unknown. The nodes are assumed to be 2/3 full, which
+/// is what random insertions produce on average, see the implementation
+/// of [`MemoryUsage`] for `BTreeMap` for the resulting error.
+fn size_of_btree_nodes<K, V>(len: usize) -> usize {
+    if len == 0 {
+        return 0;
+    }
+
+    if len <= BTREE_NODE_CAPACITY {
+        return mem::size_of::<BTreeLeafNode<K, V>>();
+    }
+
+    // Every node holds `NODE_LEN` elements, and an internal node has
+    // `NODE_LEN + 1` children.
+    const NODE_LEN: usize = BTREE_NODE_CAPACITY * 2 / 3;
+
+    let leaves = len.div_ceil(NODE_LEN + 1);
+    let mut internals = 0;
+    let mut level = leaves;
+
+    while level > 1 {
+        level = level.div_ceil(NODE_LEN + 1);
+        internals += level;
+    }
+
+    leaves * mem::size_of::<BTreeLeafNode<K, V>>()
+        + internals * mem::size_of::<BTreeInternalNode<K, V>>()
+}
+
+/// The nodes are not exposed, their size is estimated. For 1000 elements
+/// and more, the estimate is:
+///
+/// - about 8% above the allocated size after random insertions,
+/// - about 15% below after ascending or descending insertions, which
+///   leave the nodes half full,
+/// - about 60% above for a map built at once, e.g. by `collect`, whose
+///   nodes are full.
+///
+/// The error of smaller maps varies more.
+impl<K: MemoryUsage, V: MemoryUsage> MemoryUsage for BTreeMap<K, V> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // The nodes are visited as a single allocation, identified by the
+        // address of the first key.
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                self.keys().next().map(|key| key as *const K as *const ()),
+                any::type_name::<Self>(),
+                size_of_btree_nodes::<K, V>(self.len()),
+                tracker,
+                |tracker| {
+                    size_of_heap_of_elements(self.keys(), tracker)
+                        + size_of_heap_of_elements(self.values(), tracker)
+                },
+            )
+    }
+}
+
+/// The nodes are estimated like the ones of a `BTreeMap`, with the same
+/// error.
+impl<T: MemoryUsage> MemoryUsage for BTreeSet<T> {
+    fn size_of_val(&self, tracker: &mut dyn MemoryUsageTracker) -> usize {
+        // A `BTreeSet<T>` is a `BTreeMap<T, ()>`.
+        mem::size_of_val(self)
+            + size_of_heap_allocation(
+                self.iter()
+                    .next()
+                    .map(|value| value as *const T as *const ()),
+                any::type_name::<Self>(),
+                size_of_btree_nodes::<T, ()>(self.len()),
+                tracker,
+                |tracker| size_of_heap_of_elements(self.iter(), tracker),
+            )
+    }
+}
+
+#[cfg(test)]
+mod test_collection_types {
+    use super::*;
+    use crate::{check_heap_size, HeapSizeCheck};
+
+    #[test]
+    fn test_vec_deque() {
+        let mut deque = VecDeque::with_capacity(10);
+        deque.push_back(vec![1u8; 16]);
+        deque.push_front(vec![1u8; 8]);
+
+        assert_size_of_val_eq!(
+            deque,
+            mem::size_of::<VecDeque<Vec<u8>>>()
+                + deque.capacity() * mem::size_of::<Vec<u8>>()
+                + 16
+                + 8
+        );
+    }
+
+    #[test]
+    fn test_binary_heap() {
+        let mut heap = BinaryHeap::with_capacity(10);
+        heap.push(1u32);
+        heap.push(2u32);
+
+        assert_size_of_val_eq!(
+            heap,
+            mem::size_of::<BinaryHeap<u32>>() + heap.capacity() * 4
+        );
+    }
+
+    #[test]
+    fn test_linked_list() {
+        let mut list = LinkedList::new();
+        list.push_back(1u64);
+        list.push_back(2u64);
+        list.push_back(3u64);
+
+        assert_size_of_val_eq!(
+            list,
+            mem::size_of::<LinkedList<u64>>() + 3 * (2 * POINTER_BYTE_SIZE + 8)
+        );
+    }
+
+    #[test]
+    fn test_hash_table_buckets() {
+        assert_eq!(0, hash_table_buckets(0));
+        assert_eq!(4, hash_table_buckets(3));
+        assert_eq!(8, hash_table_buckets(7));
+        assert_eq!(16, hash_table_buckets(14));
+        assert_eq!(128, hash_table_buckets(112));
+
+        for len in [1, 3, 4, 7, 8, 15, 100, 1_000, 10_000] {
+            let map = HashMap::<u64, u64>::with_capacity(len);
+            let buckets = hash_table_buckets(map.capacity());
+
+            assert!(buckets.is_power_of_two());
+            assert!(buckets > map.capacity());
+        }
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let empty = HashMap::<u64, u64>::new();
+        assert_size_of_val_eq!(empty, mem::size_of::<HashMap<u64, u64>>());
+
+        let map: HashMap<u64, Vec<u8>> = (0..100).map(|nth| (nth, vec![0; 4])).collect();
+        let buckets = hash_table_buckets(map.capacity());
+
+        assert_size_of_val_eq!(
+            map,
+            mem::size_of::<HashMap<u64, Vec<u8>>>()
+                + buckets * mem::size_of::<(u64, Vec<u8>)>()
+                + buckets
+                + HASH_TABLE_GROUP_WIDTH
+                + 100 * 4
+        );
+    }
+
+    #[test]
+    fn test_hash_set() {
+        let set: HashSet<u32> = (0..3).collect();
+        assert!(check_heap_size(|| set.clone()).is_exact());
+
+        // The slots are padded to the alignment of the control bytes.
+        for capacity in [0, 1, 3, 7, 8, 100, 1_000] {
+            let check = check_heap_size(|| HashSet::<[u8; 3]>::with_capacity(capacity));
+            assert!(check.is_exact(), "{}: {:?}", capacity, check);
+
+            let check = check_heap_size(|| HashSet::<(u64, u8)>::with_capacity(capacity));
+            assert!(check.is_exact(), "{}: {:?}", capacity, check);
+        }
+    }
+
+    /// Returns `len` pseudo-random numbers, always the same ones.
+    fn random_numbers(len: usize) -> impl Iterator<Item = u64> {
+        let mut state = 1u64;
+
+        (0..len).map(move |_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+
+            state >> 16
+        })
+    }
+
+    /// Asserts that the measured bytes of `check` are `ratio` times the
+    /// allocated bytes, within 0.03.
+    fn assert_heap_size_ratio(check: HeapSizeCheck, ratio: f64) {
+        let measured_ratio = check.measured_bytes as f64 / check.allocated_bytes as f64;
+
+        assert!(
+            (measured_ratio - ratio).abs() <= 0.03,
+            "{:?} is not {} times the allocated bytes",
+            check,
+            ratio
+        );
+    }
+
+    #[test]
+    fn test_btree_map() {
+        // A B-tree of up to `BTREE_NODE_CAPACITY` elements is a single
+        // leaf, whatever the order of the insertions.
+        for len in [0usize, 1, 11] {
+            let check = check_heap_size(|| {
+                (0..len as u64)
+                    .map(|n| (n, n))
+                    .collect::<BTreeMap<u64, u64>>()
+            });
+            assert!(check.is_exact(), "{}: {:?}", len, check);
+
+            let check = check_heap_size(|| {
+                random_numbers(len)
+                    .map(|n| (n, n.to_string()))
+                    .collect::<BTreeMap<_, _>>()
+            });
+            assert!(check.is_exact(), "{}: {:?}", len, check);
+        }
+
+        // The estimate assumes random insertions, the strings are measured
+        // exactly.
+        for len in [1_000, 10_000] {
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    let mut map = BTreeMap::new();
+                    for n in random_numbers(len) {
+                        map.insert(n, n.to_string());
+                    }
+                    map
+                }),
+                1.06,
+            );
+        }
+    }
+
+    #[test]
+    fn test_btree_nodes() {
+        // The smallest B-tree with an internal node: two leaves and their
+        // parent.
+        let check = check_heap_size(|| (0..12u64).map(|n| (n, n)).collect::<BTreeMap<_, _>>());
+        assert!(check.is_exact(), "{:?}", check);
+
+        // The bounds documented by the implementation for `BTreeMap`.
+        for len in [1_000, 10_000, 100_000] {
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    let mut map = BTreeMap::new();
+                    for n in random_numbers(len) {
+                        map.insert(n, n);
+                    }
+                    map
+                }),
+                1.08,
+            );
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    let mut set = BTreeSet::new();
+                    for n in random_numbers(len) {
+                        set.insert(n);
+                    }
+                    set
+                }),
+                1.08,
+            );
+        }
+    }
+
+    #[test]
+    fn test_btree_sorted_insertions() {
+        for len in [1_000, 10_000, 100_000] {
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    let mut map = BTreeMap::new();
+                    for n in 0..len as u64 {
+                        map.insert(n, n);
+                    }
+                    map
+                }),
+                0.85,
+            );
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    let mut map = BTreeMap::new();
+                    for n in (0..len as u64).rev() {
+                        map.insert(n, n);
+                    }
+                    map
+                }),
+                0.85,
+            );
+        }
+    }
+
+    #[test]
+    fn test_btree_collect() {
+        // `collect` sorts the elements and builds the B-tree at once, in
+        // whatever order they come.
+        for len in [1_000, 10_000, 100_000] {
+            assert_heap_size_ratio(
+                check_heap_size(|| (0..len as u64).map(|n| (n, n)).collect::<BTreeMap<_, _>>()),
+                1.6,
+            );
+            assert_heap_size_ratio(
+                check_heap_size(|| {
+                    random_numbers(len)
+                        .map(|n| (n, n))
+                        .collect::<BTreeMap<_, _>>()
+                }),
+                1.6,
+            );
+            assert_heap_size_ratio(
+                check_heap_size(|| (0..len as u64).collect::<BTreeSet<_>>()),
+                1.6,
+            );
+        }
+    }
+
+    #[test]
+    fn test_btree_set() {
+        let set: BTreeSet<u64> = (0..5).collect();
+
+        assert_size_of_val_eq!(
+            set,
+            mem::size_of::<BTreeSet<u64>>() + mem::size_of::<BTreeLeafNode<u64, ()>>()
+        );
+    }
+}
+
+impl<T> MemoryUsage for std::marker::PhantomData<T> {
+    fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod test_usable_sizes {
+    use super::*;
+    use crate::Tracker;
+
+    fn usable_size_of_val<T: MemoryUsage>(value: &T) -> usize {
+        // SAFETY: the global allocator of the tests is a
+        // `CountingAllocator::system()`, which forwards every allocation to
+        // `System` unchanged, so the blocks are allocated by `System`.
+        value.size_of_val(&mut unsafe { Tracker::new().with_usable_sizes() })
+    }
+
+    fn estimated_size_of_val<T: MemoryUsage>(value: &T) -> usize {
+        value.size_of_val(&mut Tracker::new())
+    }
+
+    #[test]
+    fn test_usable_sizes_are_not_smaller() {
+        let vec = Vec::<u32>::with_capacity(3);
+        let string = String::from("loupe");
+        let boxed = Box::new(1u8);
+        let rc = Rc::new([1u8; 26]);
+        let arc = Arc::new(vec![1u64; 5]);
+
+        assert!(usable_size_of_val(&vec) >= estimated_size_of_val(&vec));
+        assert!(usable_size_of_val(&string) >= estimated_size_of_val(&string));
+        assert!(usable_size_of_val(&boxed) >= estimated_size_of_val(&boxed));
+        assert!(usable_size_of_val(&rc) >= estimated_size_of_val(&rc));
+        assert!(usable_size_of_val(&arc) >= estimated_size_of_val(&arc));
+    }
+
+    #[test]
+    fn test_empty_allocations() {
+        assert_eq!(
+            usable_size_of_val(&Vec::<u8>::new()),
+            mem::size_of::<Vec<u8>>()
+        );
+        assert_eq!(usable_size_of_val(&Box::new(())), mem::size_of::<Box<()>>());
+    }
+
+    // The smallest block of glibc has 24 usable bytes on 64-bit targets.
+    #[cfg(all(target_os = "linux", target_env = "gnu", target_pointer_width = "64"))]
+    #[test]
+    fn test_allocator_rounding() {
+        assert_eq!(usable_size_of_val(&Box::new(1u8)), POINTER_BYTE_SIZE + 24);
+        assert_eq!(
+            usable_size_of_val(&Vec::<u8>::with_capacity(3)),
+            mem::size_of::<Vec<u8>>() + 24
+        );
+    }
+
+    #[test]
+    fn test_shared_allocations_are_estimated() {
+        let rc = Rc::new(1u8);
+        let arc = Arc::new(String::from("loupe"));
+
+        assert_eq!(usable_size_of_val(&rc), estimated_size_of_val(&rc));
+        assert_eq!(
+            usable_size_of_val(&arc) - usable_size_of_val(&*arc),
+            estimated_size_of_val(&arc) - estimated_size_of_val(&*arc)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_allocations {
+    use super::*;
+    use crate::check_heap_size;
+
+    macro_rules! assert_heap_size_is_exact {
+        ($build:expr) => {
+            let check = check_heap_size(|| $build);
+
+            assert!(check.is_exact(), "`{}`: {:?}", stringify!($build), check);
+        };
+    }
+
+    #[test]
+    fn test_vec() {
+        assert_heap_size_is_exact!(vec![1u32, 2, 3]);
+        assert_heap_size_is_exact!({
+            let mut vec = Vec::<u32>::with_capacity(10);
+            vec.extend([1, 2, 3].iter().copied());
+            vec
+        });
+        assert_heap_size_is_exact!(vec![String::from("a"), String::from("bc")]);
+        assert_heap_size_is_exact!(VecDeque::<u64>::with_capacity(7));
+        assert_heap_size_is_exact!([3u8, 1, 2].iter().copied().collect::<BinaryHeap<_>>());
+        assert_heap_size_is_exact!([1u16, 2, 3].iter().copied().collect::<LinkedList<_>>());
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_heap_size_is_exact!(String::with_capacity(13));
+        assert_heap_size_is_exact!(String::from("loupe").into_boxed_str());
+        assert_heap_size_is_exact!(CString::new("loupe").unwrap());
+        assert_heap_size_is_exact!(PathBuf::from("/a/b"));
+    }
+
+    #[test]
+    fn test_pointers() {
+        assert_heap_size_is_exact!(Box::new(1u64));
+        assert_heap_size_is_exact!(Box::new(vec![1u8; 5]));
+        assert_heap_size_is_exact!(Rc::new([1u8; 3]));
+        assert_heap_size_is_exact!(Arc::new(String::from("loupe")));
+        assert_heap_size_is_exact!({
+            let shared = Rc::new(1u64);
+            (shared.clone(), shared)
+        });
+    }
+
+    #[test]
+    fn test_maps() {
+        assert_heap_size_is_exact!(HashMap::<u8, u64>::with_capacity(5));
+        assert_heap_size_is_exact!((0..100u32).map(|n| (n, n)).collect::<HashMap<_, _>>());
+        assert_heap_size_is_exact!((0..3u64).collect::<HashSet<_>>());
+
+        // A B-tree of up to `BTREE_NODE_CAPACITY` elements is a single leaf.
+        assert_heap_size_is_exact!((0..5u64).map(|n| (n, n)).collect::<BTreeMap<_, _>>());
+    }
+
+    #[test]
+    fn test_undercount() {
+        struct Undercounted(Vec<u8>);
+
+        impl MemoryUsage for Undercounted {
+            fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+                mem::size_of_val(self) + self.0.len()
+            }
+        }
+
+        let check = check_heap_size(|| {
+            let mut vec = Vec::with_capacity(8);
+            vec.push(1);
+            Undercounted(vec)
+        });
+
+        assert_eq!(check.allocated_bytes, 8);
+        assert_eq!(check.measured_bytes, 1);
+        assert!(!check.is_exact());
+    }
+}
+
+#[cfg(test)]
+mod test_breakdown {
+    use super::*;
+    use crate::{breakdown, Breakdown, Tracker};
+
+    #[test]
+    fn test_owned() {
+        let value = (Box::new(1u64), String::from("loupe"));
+
+        assert_eq!(
+            breakdown(&value),
+            Breakdown {
+                inline_bytes: mem::size_of_val(&value),
+                heap_bytes: 8 + 5,
+                shared_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_shared() {
+        let inner = Rc::new(1u64);
+        let outer = Rc::new(vec![inner.clone(), inner]);
+        let value = (Box::new(1u8), outer);
+
+        // The inner allocation is part of the outer one.
+        let inner_size = 2 * POINTER_BYTE_SIZE + 8;
+        let outer_size = 2 * POINTER_BYTE_SIZE + mem::size_of::<Vec<Rc<u64>>>();
+
+        assert_eq!(
+            breakdown(&value),
+            Breakdown {
+                inline_bytes: mem::size_of_val(&value),
+                heap_bytes: 1,
+                shared_bytes: outer_size + 2 * POINTER_BYTE_SIZE + inner_size,
+            }
+        );
+    }
+
+    #[test]
+    fn test_borrowed() {
+        let pointee = vec![1u8; 3];
+        let value = (&pointee, &pointee);
+
+        assert_eq!(
+            breakdown(&value),
+            Breakdown {
+                inline_bytes: 2 * POINTER_BYTE_SIZE,
+                heap_bytes: 0,
+                shared_bytes: mem::size_of::<Vec<u8>>() + 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_total() {
+        let shared = Arc::new(String::from("shared"));
+        let value = vec![shared.clone(), shared];
+
+        for policy in [
+            SharedPolicy::FirstOwner,
+            SharedPolicy::EvenSplit,
+            SharedPolicy::Unowned,
+        ]
+        .iter()
+        .copied()
+        {
+            let breakdown = Tracker::new()
+                .with_shared_policy(policy)
+                .measure_breakdown(&value)
+                .unwrap();
+            let size = value.size_of_val(&mut Tracker::new().with_shared_policy(policy));
+
+            assert_eq!(breakdown.total_bytes(), size, "{:?}", policy);
+            assert_eq!(breakdown.heap_bytes, 2 * POINTER_BYTE_SIZE, "{:?}", policy);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_visitor {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        HeapAllocation(*const (), usize),
+        ExitHeapAllocation,
+        SharedReference(*const ()),
+        ExitSharedReference(usize),
+        BorrowedReference(*const ()),
+        ExitBorrowedReference(usize),
+    }
+
+    #[derive(Default)]
+    struct RecordingTracker {
+        visited: BTreeSet<*const ()>,
+        events: Vec<Event>,
+    }
+
+    impl MemoryUsageTracker for RecordingTracker {
+        fn track(&mut self, address: *const ()) -> bool {
+            self.visited.insert(address)
+        }
+
+        fn heap_allocation(&mut self, address: *const (), _: &'static str, bytes: usize) {
+            self.events.push(Event::HeapAllocation(address, bytes));
+        }
+
+        fn exit_heap_allocation(&mut self) {
+            self.events.push(Event::ExitHeapAllocation);
+        }
+
+        fn shared_reference(&mut self, address: *const ()) {
+            self.events.push(Event::SharedReference(address));
+        }
+
+        fn exit_shared_reference(&mut self, bytes: usize) {
+            self.events.push(Event::ExitSharedReference(bytes));
+        }
+
+        fn borrowed_reference(&mut self, address: *const (), _: &'static str) {
+            self.events.push(Event::BorrowedReference(address));
+        }
+
+        fn exit_borrowed_reference(&mut self, bytes: usize) {
+            self.events.push(Event::ExitBorrowedReference(bytes));
+        }
+    }
+
+    fn events_of<T: MemoryUsage>(value: &T) -> Vec<Event> {
+        let mut tracker = RecordingTracker::default();
+        value.size_of_val(&mut tracker);
+
+        tracker.events
+    }
+
+    fn address_of<T: ?Sized>(value: &T) -> *const () {
+        value as *const T as *const ()
+    }
+
+    #[test]
+    fn test_nested_heap_allocations() {
+        let value = vec![Box::new(1u32), Box::new(2u32)];
+
+        assert_eq!(
+            events_of(&value),
+            [
+                Event::HeapAllocation(address_of(value.as_slice()), 2 * POINTER_BYTE_SIZE),
+                Event::HeapAllocation(address_of(&*value[0]), 4),
+                Event::ExitHeapAllocation,
+                Event::HeapAllocation(address_of(&*value[1]), 4),
+                Event::ExitHeapAllocation,
+                Event::ExitHeapAllocation,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_heap_allocations() {
+        assert_eq!(events_of(&Vec::<u8>::new()), []);
+        assert_eq!(events_of(&Box::new(())), []);
+        assert_eq!(events_of(&HashMap::<u8, u8>::with_capacity(8)), []);
+    }
+
+    #[test]
+    fn test_shared_reference() {
+        let shared = Rc::new(String::from("shared"));
+        let value = vec![shared.clone(), shared];
+        let address = address_of(&*value[0]);
+        let shared_size = 2 * POINTER_BYTE_SIZE + mem::size_of::<String>() + 6;
+
+        assert_eq!(
+            events_of(&value),
+            [
+                Event::HeapAllocation(address_of(value.as_slice()), 2 * POINTER_BYTE_SIZE),
+                Event::SharedReference(address),
+                Event::HeapAllocation(address, 2 * POINTER_BYTE_SIZE + mem::size_of::<String>()),
+                Event::HeapAllocation(address_of(value[0].as_str()), 6),
+                Event::ExitHeapAllocation,
+                Event::ExitHeapAllocation,
+                Event::ExitSharedReference(shared_size),
+                Event::SharedReference(address),
+                Event::ExitSharedReference(0),
+                Event::ExitHeapAllocation,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_borrowed_reference() {
+        let value = 1u64;
+        let references = (&value, &value);
+
+        assert_eq!(
+            events_of(&references),
+            [
+                Event::BorrowedReference(address_of(&value)),
+                Event::ExitBorrowedReference(8),
+                Event::BorrowedReference(address_of(&value)),
+                Event::ExitBorrowedReference(0),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_budget {
+    use super::*;
+    use crate::{MeasureError, Tracker};
+
+    #[test]
+    fn test_within_budget() {
+        let value = vec![String::from("abc"); 4];
+        let size = value.size_of_val(&mut Tracker::new());
+
+        assert_eq!(Tracker::new().measure_within(&value, size), Ok(size));
+        assert_eq!(
+            Tracker::new().measure_within(&value, size - 1),
+            Err(MeasureError::BudgetExceeded {
+                path: String::new(),
+                budget: size - 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_stop_early() {
+        let value = (0..100).map(Rc::new).collect::<Vec<Rc<u64>>>();
+        let mut tracker = Tracker::new();
+
+        assert!(tracker.measure_within(&value, 1024).is_err());
+
+        // The first values are visited until the budget is exceeded.
+        let address = |value: &Rc<u64>| &**value as *const u64 as *const ();
+        assert!(!tracker.track_value(address(&value[0]), 8));
+        assert!(tracker.track_value(address(&value[99]), 8));
+    }
+
+    #[test]
+    fn test_borrowed_value() {
+        let value = [7u8; 64];
+        let reference = (&value, 1u8);
+
+        assert_eq!(
+            Tracker::new().measure_within(&reference, 32),
+            Err(MeasureError::BudgetExceeded {
+                path: String::new(),
+                budget: 32,
+            })
+        );
+        assert_eq!(
+            Tracker::new()
+                .measure_within(&reference, 32)
+                .unwrap_err()
+                .to_string(),
+            "the budget of 32 bytes has been exceeded at <root>"
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_limits {
+    use super::*;
+    use crate::Tracker;
+
+    #[test]
+    fn test_max_depth() {
+        let value = vec![vec![String::from("abc")], vec![String::from("de")]];
+        let strings = 2 * mem::size_of::<String>();
+        let vecs = 2 * mem::size_of::<Vec<String>>();
+
+        let mut tracker = Tracker::new().with_max_depth(1);
+        let size = value.size_of_val(&mut tracker);
+
+        // The buffers of the inner `Vec`s are counted, not what they own.
+        assert_eq!(size, mem::size_of_val(&value) + vecs + strings);
+        assert_eq!(
+            tracker.truncated_values(),
+            [any::type_name::<String>(), any::type_name::<String>()]
+        );
+
+        let mut tracker = Tracker::new().with_max_depth(2);
+        assert_eq!(
+            value.size_of_val(&mut tracker),
+            value.size_of_val(&mut Tracker::new())
+        );
+        assert!(!tracker.is_truncated());
+    }
+
+    #[test]
+    fn test_max_depth_of_references() {
+        let value = String::from("abc");
+        let reference = &&value;
+
+        // The inline size of a pointee is counted by the reference.
+        let mut tracker = Tracker::new().with_max_depth(1);
+        let size = MemoryUsage::size_of_val(&reference, &mut tracker);
+
+        assert_eq!(size, 2 * POINTER_BYTE_SIZE + mem::size_of::<String>());
+        assert_eq!(tracker.truncated_values(), [any::type_name::<String>()]);
+    }
+
+    #[test]
+    fn test_max_values() {
+        let value = vec![String::from("abc"); 100];
+
+        let mut tracker = Tracker::new().with_max_values(10);
+        let size = value.size_of_val(&mut tracker);
+
+        assert_eq!(
+            size,
+            mem::size_of_val(&value) + 100 * mem::size_of::<String>() + 10 * 3
+        );
+        assert_eq!(tracker.truncated_values(), [any::type_name::<String>()]);
+        assert!(size < value.size_of_val(&mut Tracker::new()));
+    }
+}
+
+#[cfg(test)]
+mod test_sampling {
+    use super::*;
+    use crate::Tracker;
+
+    #[test]
+    fn test_uniform_elements() {
+        let value = vec![String::from("abc"); 1000];
+
+        let mut tracker = Tracker::new().with_sampling(10);
+        assert_eq!(
+            value.size_of_val(&mut tracker),
+            value.size_of_val(&mut Tracker::new())
+        );
+        assert_eq!(tracker.sampling_margin(), 0);
+    }
+
+    #[test]
+    fn test_counting_trackers() {
+        // The values are counted one by one, the elements are all measured.
+        let value = (0..1000).map(|i| "a".repeat(i % 7)).collect::<Vec<_>>();
+        let size = value.size_of_val(&mut Tracker::new());
+
+        let histogram = Tracker::new().with_sampling(10).histogram(&value);
+        let strings = histogram.get(any::type_name::<String>()).unwrap();
+        assert_eq!(strings.count, 1000);
+        assert_eq!(strings.total_bytes(), size - mem::size_of_val(&value));
+
+        let report = Tracker::new().with_sampling(10).report(&value);
+        assert_eq!(report.total_bytes(), size);
+
+        let mut tracker = Tracker::new().with_sampling(10);
+        let graph = tracker.graph(&value);
+        assert_eq!(
+            graph.nodes().len(),
+            1 + 1 + (0..1000).filter(|i| i % 7 > 0).count()
+        );
+        assert_eq!(tracker.sampling_margin(), 0);
+    }
+
+    #[test]
+    fn test_small_collection() {
+        let value = (0..10).map(|i| "a".repeat(i)).collect::<Vec<_>>();
+
+        let mut tracker = Tracker::new().with_sampling(10);
+        assert_eq!(
+            value.size_of_val(&mut tracker),
+            value.size_of_val(&mut Tracker::new())
+        );
+        assert_eq!(tracker.sampling_margin(), 0);
+    }
+
+    #[test]
+    fn test_varying_elements() {
+        let value = (0..1000).map(|i| "a".repeat(i % 7)).collect::<Vec<_>>();
+        let size = value.size_of_val(&mut Tracker::new());
+
+        let mut tracker = Tracker::new().with_sampling(50);
+        let estimate = value.size_of_val(&mut tracker);
+
+        assert!(tracker.sampling_margin() > 0);
+        assert!(estimate.abs_diff(size) <= tracker.sampling_margin());
+    }
+
+    #[test]
+    fn test_map() {
+        let value = (0..1000u32)
+            .map(|i| (i, String::from("abc")))
+            .collect::<HashMap<_, _>>();
+
+        let mut tracker = Tracker::new().with_sampling(10);
+        assert_eq!(
+            value.size_of_val(&mut tracker),
+            value.size_of_val(&mut Tracker::new())
+        );
+    }
+
+    #[test]
+    fn test_shared_elements() {
+        let shared = Rc::new(String::from("abc"));
+        let value = vec![shared; 1000];
+
+        // The shared allocation is charged once, not once per sampled
+        // element.
+        let mut tracker = Tracker::new().with_sampling(10);
+        assert_eq!(
+            value.size_of_val(&mut tracker),
+            value.size_of_val(&mut Tracker::new())
+        );
+        assert_eq!(tracker.sampling_margin(), 0);
+
+        let value = (0..1000)
+            .map(|i| (Rc::clone(&value[0]), "a".repeat(i % 7)))
+            .collect::<Vec<_>>();
+        let size = value.size_of_val(&mut Tracker::new());
+
+        let mut tracker = Tracker::new().with_sampling(50);
+        let estimate = value.size_of_val(&mut tracker);
+        assert!(estimate.abs_diff(size) <= tracker.sampling_margin());
+    }
+
+    #[test]
+    #[should_panic(expected = "the sample size must be at least 2")]
+    fn test_sample_size_too_small() {
+        let _ = Tracker::new().with_sampling(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[derive(Copy, Clone)]
+    struct TestMemoryUsage {
+        // Must be greater than or equal to mem::size_of::<TestMemoryUsage>() or else MemoryUsage may overflow.
+        pub size_to_report: usize,
+    }
+    impl MemoryUsage for TestMemoryUsage {
+        fn size_of_val(&self, _: &mut dyn MemoryUsageTracker) -> usize {
+            // Try to prevent buggy tests before they're hard to debug.
+            assert!(self.size_to_report >= mem::size_of::<TestMemoryUsage>());
+            self.size_to_report
+        }
+    }
+
+    #[test]
+    fn test_ints() {
+        assert_eq!(MemoryUsage::size_of_val(&32, &mut BTreeSet::new()), 4);
+        assert_eq!(32.size_of_val(&mut BTreeSet::new()), 4);
+    }
+
+    #[test]
+    fn test_arrays() {
+        let x: [[u8; 7]; 13] = [[0; 7]; 13];
+        assert_eq!(7 * 13, mem::size_of_val(&x));
+        assert_eq!(7 * 13, MemoryUsage::size_of_val(&x, &mut BTreeSet::new()));
+    }
+
+    #[test]
+    fn test_slice_no_static_size() {
+        {
+            let x: [u8; 13] = [0; 13];
+            let y: &[u8] = &x;
+            assert_eq!(13, mem::size_of_val(y));
+            assert_eq!(13, MemoryUsage::size_of_val(y, &mut BTreeSet::new()));
+        }
+
+        {
+            let mut x: [TestMemoryUsage; 13] = [TestMemoryUsage {
+                size_to_report: mem::size_of::<TestMemoryUsage>(),
+            }; 13];
+            x[0].size_to_report += 7;
+            let y: &[TestMemoryUsage] = &x;
+            assert_eq!(13 * mem::size_of::<TestMemoryUsage>(), mem::size_of_val(y));
+            assert_eq!(
+                13 * mem::size_of::<TestMemoryUsage>() + 7,
+                MemoryUsage::size_of_val(y, &mut BTreeSet::new())
+            );
+        }
+    }
+
+    #[test]
+    fn test_vecs() {
+        let mut x = vec![];
+        let empty_vec_size = mem::size_of_val(&x);
+        let tmu_size = mem::size_of::<TestMemoryUsage>();
+        x.push(TestMemoryUsage {
+            size_to_report: tmu_size + 3,
+        });
+        x.push(TestMemoryUsage {
+            size_to_report: tmu_size + 7,
+        });
+        assert_eq!(empty_vec_size, mem::size_of_val(&x));
+        assert_eq!(
+            empty_vec_size + x.capacity() * tmu_size + 3 + 7,
+            MemoryUsage::size_of_val(&x, &mut BTreeSet::new())
+        );
+    }
+
+    #[test]
+    fn test_vec_capacity() {
+        let mut x: Vec<u64> = Vec::with_capacity(1_000);
+        x.push(1);
+        x.push(2);
+        x.push(3);
+        assert_eq!(
+            mem::size_of_val(&x) + 1_000 * 8,
+            MemoryUsage::size_of_val(&x, &mut BTreeSet::new())
+        );
+
+        x.shrink_to_fit();
+        assert_eq!(
+            mem::size_of_val(&x) + 3 * 8,
+            MemoryUsage::size_of_val(&x, &mut BTreeSet::new())
+        );
+    }
+
+    #[test]
+    fn test_vec_unused_capacity() {
+        let mut x: Vec<Vec<u32>> = Vec::with_capacity(10);
+        x.push(Vec::with_capacity(5));
+        x[0].push(1);
+
+        let mut tracker = UnusedCapacityTracker::default();
+        assert_eq!(
+            mem::size_of_val(&x) + 10 * mem::size_of::<Vec<u32>>() + 5 * 4,
+            MemoryUsage::size_of_val(&x, &mut tracker)
+        );
+        assert_eq!(9 * mem::size_of::<Vec<u32>>() + 4 * 4, tracker.unused);
+    }
+
+    #[test]
+    fn test_double_counting() {
         let tmu_size = mem::size_of::<TestMemoryUsage>();
         let x = TestMemoryUsage {
             size_to_report: tmu_size + 7,
@@ -312,23 +3036,74 @@ mod tests {
         let y = TestMemoryUsage {
             size_to_report: tmu_size + 7,
         };
-        let mut v = vec![];
+        let mut v = Vec::with_capacity(3);
         let empty_vec_size = mem::size_of_val(&v);
+        let buffer_size = 3 * mem::size_of::<&TestMemoryUsage>();
         v.push(&x);
         assert_eq!(
-            empty_vec_size + 1 * mem::size_of_val(&x) + 1 * (tmu_size + 7),
+            empty_vec_size + buffer_size + (tmu_size + 7),
             MemoryUsage::size_of_val(&v, &mut BTreeSet::new())
         );
         v.push(&x);
         assert_eq!(
-            empty_vec_size + 2 * mem::size_of_val(&x) + 1 * (tmu_size + 7),
+            empty_vec_size + buffer_size + (tmu_size + 7),
             MemoryUsage::size_of_val(&v, &mut BTreeSet::new())
         );
         v.push(&y);
-        assert_eq!(mem::size_of_val(&x), mem::size_of_val(&y));
         assert_eq!(
-            empty_vec_size + 3 * mem::size_of_val(&x) + 2 * (tmu_size + 7),
+            empty_vec_size + buffer_size + 2 * (tmu_size + 7),
             MemoryUsage::size_of_val(&v, &mut BTreeSet::new())
         );
     }
+
+    #[test]
+    fn test_double_counting_with_sizes() {
+        let x = 1u64;
+        let v = vec![&x, &x];
+
+        assert_eq!(
+            mem::size_of_val(&v) + 2 * POINTER_BYTE_SIZE + 8,
+            MemoryUsage::size_of_val(&v, &mut crate::Tracker::new())
+        );
+    }
+
+    #[test]
+    fn test_struct_and_first_field() {
+        let pair = (1u64, 2u64);
+        let first = if &pair.0 as *const u64 as *const () == &pair as *const _ as *const () {
+            &pair.0
+        } else {
+            &pair.1
+        };
+        let references = (first, &pair);
+
+        // Both share an address. An address-only tracker skips the pair,
+        // an address and size tracker counts the first field twice.
+        assert_eq!(
+            2 * POINTER_BYTE_SIZE + 8,
+            MemoryUsage::size_of_val(&references, &mut BTreeSet::new())
+        );
+        assert_eq!(
+            2 * POINTER_BYTE_SIZE + 8 + 16,
+            MemoryUsage::size_of_val(&references, &mut crate::Tracker::new())
+        );
+    }
+
+    #[test]
+    fn test_zero_sized_value() {
+        let values = [Box::new(1u64), Box::new(2u64)];
+        let empty: &[Box<u64>] = &values[..0];
+        let references = (empty, &values[0]);
+
+        // The empty slice is at the address of the first value. An
+        // address-only tracker skips the value.
+        assert_eq!(
+            3 * POINTER_BYTE_SIZE,
+            MemoryUsage::size_of_val(&references, &mut BTreeSet::new())
+        );
+        assert_eq!(
+            3 * POINTER_BYTE_SIZE + POINTER_BYTE_SIZE + 8,
+            MemoryUsage::size_of_val(&references, &mut crate::Tracker::new())
+        );
+    }
 }
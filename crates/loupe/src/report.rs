@@ -0,0 +1,379 @@
+use crate::{MemoryUsage, MemoryUsageTracker, Tracker};
+use std::any;
+use std::fmt;
+use std::io;
+use std::mem;
+
+/// Returns a [`Report`] of the memory used by `value`, field by field.
+///
+/// It uses a default [`Tracker`], see [`Tracker::report`] to configure it.
+///
+/// ```rust
+/// use loupe::MemoryUsage;
+/// use loupe_derive::MemoryUsage;
+///
+/// #[derive(MemoryUsage)]
+/// struct Cache {
+///     keys: Vec<u32>,
+///     name: String,
+/// }
+///
+/// let cache = Cache {
+///     keys: Vec::with_capacity(1024),
+///     name: String::from("cache"),
+/// };
+///
+/// let report = loupe::report(&cache);
+/// let keys = report.root().get(&["keys"]).unwrap();
+///
+/// assert_eq!(keys.heap_bytes(), 4096);
+/// assert_eq!(report.total_bytes(), cache.size_of_val(&mut loupe::Tracker::new()));
+/// ```
+pub fn report<T>(value: &T) -> Report
+where
+    T: MemoryUsage + ?Sized,
+{
+    Tracker::new().report(value)
+}
+
+/// The memory used by a value, as a tree of its fields.
+///
+/// Every byte of a node is also counted by its parent, so the total of a
+/// report is the size of its root, as returned by
+/// [`MemoryUsage::size_of_val`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Report {
+    root: ReportNode,
+}
+
+impl Report {
+    /// Returns the node of the measured value.
+    pub fn root(&self) -> &ReportNode {
+        &self.root
+    }
+
+    /// Returns the size of the measured value in bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.root.total_bytes()
+    }
+
+    /// Returns whether values have not been visited because of the limits
+    /// of the tracker, in which case the sizes are lower bounds. See
+    /// [`ReportNode::is_truncated`].
+    pub fn is_truncated(&self) -> bool {
+        let mut truncated = false;
+        self.root
+            .visit(&mut Vec::new(), &mut |_, node| truncated |= node.truncated);
+
+        truncated
+    }
+}
+
+impl Report {
+    /// Writes the report as folded stacks, as read by `inferno` or
+    /// `flamegraph.pl`, to render it as a flame graph.
+    ///
+    /// There is a line per node, `root;field;subfield bytes`, where `root`
+    /// is the type name of the measured value, and `bytes` are the bytes of
+    /// the node not counted by its children. Lines with no bytes are
+    /// omitted.
+    pub fn write_folded<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut result = Ok(());
+        let root = frame(self.root.type_name);
+
+        self.root.visit(&mut Vec::new(), &mut |path, node| {
+            let children_bytes = node
+                .children
+                .iter()
+                .map(ReportNode::total_bytes)
+                .sum::<usize>();
+            let bytes = node.total_bytes().saturating_sub(children_bytes);
+
+            if result.is_err() || bytes == 0 {
+                return;
+            }
+
+            let stack = std::iter::once(root.clone())
+                .chain(path.iter().map(|name| frame(name)))
+                .collect::<Vec<_>>()
+                .join(";");
+
+            result = writeln!(writer, "{} {}", stack, bytes);
+        });
+
+        result
+    }
+
+    /// Returns the report as folded stacks, see [`Report::write_folded`].
+    pub fn to_folded(&self) -> String {
+        let mut folded = Vec::new();
+        self.write_folded(&mut folded)
+            .expect("writing to a `Vec` never fails");
+
+        String::from_utf8(folded).expect("folded stacks are UTF-8")
+    }
+}
+
+/// Returns `name` as a frame of a folded stack, where `;` separates the
+/// frames, e.g. in `[u8; 4]`.
+fn frame(name: &str) -> String {
+    name.replace(';', ",")
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        self.root.fmt_tree(formatter, 0)
+    }
+}
+
+/// A node of a [`Report`], i.e. a measured field.
+///
+/// The fields of the values owned by a field, e.g. of the items of a
+/// `Vec`, are merged by name into a single node, so that the size of the
+/// tree is bounded by the size of the types, not of the values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportNode {
+    name: &'static str,
+    type_name: &'static str,
+    variant: Option<&'static str>,
+    count: usize,
+    inline_bytes: usize,
+    heap_bytes: usize,
+    truncated: bool,
+    children: Vec<ReportNode>,
+}
+
+impl ReportNode {
+    fn new(name: &'static str, type_name: &'static str) -> Self {
+        Self {
+            name,
+            type_name,
+            variant: None,
+            count: 1,
+            inline_bytes: 0,
+            heap_bytes: 0,
+            truncated: false,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns the name of the field, or an empty string for the root.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the name of the type of the field.
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// Returns the variant of the field if it is an `enum`. When values in
+    /// different variants are merged, it is the last one visited.
+    pub fn variant(&self) -> Option<&'static str> {
+        self.variant
+    }
+
+    /// Returns the number of values merged into the field, e.g. the
+    /// number of items of a `Vec` for a field of its items.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the inline size of the field in bytes.
+    pub fn inline_bytes(&self) -> usize {
+        self.inline_bytes
+    }
+
+    /// Returns the number of bytes owned by the field outside of its
+    /// inline size.
+    pub fn heap_bytes(&self) -> usize {
+        self.heap_bytes
+    }
+
+    /// Returns the size of the field in bytes, i.e. its inline and heap
+    /// bytes.
+    pub fn total_bytes(&self) -> usize {
+        self.inline_bytes + self.heap_bytes
+    }
+
+    /// Returns whether values contained in the field have not been visited
+    /// because of the limits of the tracker, see
+    /// [`Tracker::with_max_depth`] and [`Tracker::with_max_values`]. The
+    /// size of the field and of its parents are then lower bounds.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the fields of the field, in the order they have been
+    /// visited.
+    pub fn children(&self) -> &[ReportNode] {
+        &self.children
+    }
+
+    /// Returns the descendant at `path`, a list of field names, e.g.
+    /// `&["inner", "0", "items"]`.
+    pub fn get(&self, path: &[&str]) -> Option<&ReportNode> {
+        path.iter().try_fold(self, |node, name| {
+            node.children.iter().find(|child| child.name == *name)
+        })
+    }
+
+    /// Calls `visit` on this node and all its descendants, depth first,
+    /// with their path from this node.
+    pub(crate) fn visit<F>(&self, path: &mut Vec<&'static str>, visit: &mut F)
+    where
+        F: FnMut(&[&'static str], &ReportNode),
+    {
+        visit(path, self);
+
+        for child in &self.children {
+            path.push(child.name);
+            child.visit(path, visit);
+            path.pop();
+        }
+    }
+
+    /// Adds `child` to the children, merging it with a child of the same
+    /// name if any.
+    fn merge_child(&mut self, child: ReportNode) {
+        match self
+            .children
+            .iter_mut()
+            .find(|existing| existing.name == child.name)
+        {
+            Some(existing) => {
+                existing.variant = child.variant.or(existing.variant);
+                existing.count += child.count;
+                existing.inline_bytes += child.inline_bytes;
+                existing.heap_bytes += child.heap_bytes;
+                existing.truncated |= child.truncated;
+
+                for grandchild in child.children {
+                    existing.merge_child(grandchild);
+                }
+            }
+
+            None => self.children.push(child),
+        }
+    }
+
+    fn fmt_tree(&self, formatter: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        write!(formatter, "{:indent$}", "", indent = depth * 2)?;
+
+        if !self.name.is_empty() {
+            write!(formatter, "{}: ", self.name)?;
+        }
+
+        write!(formatter, "{}", self.type_name)?;
+
+        if let Some(variant) = self.variant {
+            write!(formatter, "::{}", variant)?;
+        }
+
+        write!(
+            formatter,
+            " = {} bytes ({} inline, {} heap",
+            self.total_bytes(),
+            self.inline_bytes,
+            self.heap_bytes
+        )?;
+
+        if self.truncated {
+            write!(formatter, ", truncated")?;
+        }
+
+        writeln!(formatter, ")")?;
+
+        self.children
+            .iter()
+            .try_for_each(|child| child.fmt_tree(formatter, depth + 1))
+    }
+}
+
+/// A [`MemoryUsageTracker`] building a [`Report`] from the fields entered
+/// and exited, forwarding everything else to a [`Tracker`].
+struct ReportTracker<'a> {
+    tracker: &'a mut Tracker,
+    /// The entered fields, the root being the first one.
+    stack: Vec<ReportNode>,
+}
+
+impl MemoryUsageTracker for ReportTracker<'_> {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.tracker.track(address)
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn sample_size(&self) -> Option<usize> {
+        // The fields are counted as they are measured: sampling the elements
+        // of the collections would only count the sampled ones.
+        None
+    }
+
+    fn enter_field(&mut self, name: &'static str, type_name: &'static str) {
+        self.stack.push(ReportNode::new(name, type_name));
+        self.tracker.enter_field(name, type_name);
+    }
+
+    fn variant(&mut self, name: &'static str) {
+        if let Some(node) = self.stack.last_mut() {
+            node.variant = Some(name);
+        }
+
+        self.tracker.variant(name);
+    }
+
+    fn exit_field(&mut self, inline_bytes: usize, heap_bytes: usize) {
+        self.tracker.exit_field(inline_bytes, heap_bytes);
+
+        // The root is never exited by a field.
+        if self.stack.len() < 2 {
+            return;
+        }
+
+        let mut node = self.stack.pop().unwrap();
+        node.inline_bytes = inline_bytes;
+        node.heap_bytes = heap_bytes;
+
+        self.stack.last_mut().unwrap().merge_child(node);
+    }
+
+    fn record_truncated(&mut self, type_name: &'static str) {
+        if let Some(node) = self.stack.last_mut() {
+            node.truncated = true;
+        }
+
+        self.tracker.record_truncated(type_name);
+    }
+}
+
+impl Tracker {
+    /// Returns a [`Report`] of the memory used by `value`, field by field,
+    /// measured with this tracker. See [`report`].
+    ///
+    /// The elements of the collections are never sampled, see
+    /// [`Tracker::with_sampling`].
+    pub fn report<T>(&mut self, value: &T) -> Report
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        let mut tracker = ReportTracker {
+            tracker: self,
+            stack: vec![ReportNode::new("", any::type_name::<T>())],
+        };
+
+        let size = value.size_of_val(&mut tracker);
+        let mut root = tracker.stack.pop().unwrap();
+        root.inline_bytes = mem::size_of_val(value);
+        root.heap_bytes = size - root.inline_bytes;
+
+        Report { root }
+    }
+}
@@ -0,0 +1,408 @@
+use crate::usable_size::usable_size;
+use crate::{LockPolicy, MemoryUsage, MemoryUsageTracker, SharedPolicy};
+use std::alloc::Layout;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+
+/// A configurable [`MemoryUsageTracker`].
+///
+/// Like a `BTreeSet<*const ()>`, it tracks the visited addresses so that
+/// a value is measured only once. Unlike it, the values reached through
+/// references and shared pointers are tracked by address and size, see
+/// [`MemoryUsageTracker::track_value`]. In addition, it can be configured
+/// with a [`SharedPolicy`] and a [`LockPolicy`], limits on the depth and
+/// number of visited values, and a sample size for collections, and it
+/// collects the shared allocations and the unmeasured and truncated values
+/// it visits.
+///
+/// ```rust
+/// use loupe::{MemoryUsage, SharedPolicy, Tracker};
+/// use std::rc::Rc;
+///
+/// let shared = Rc::new(42u64);
+/// let value = vec![shared.clone(), shared];
+///
+/// let mut tracker = Tracker::new().with_shared_policy(SharedPolicy::Unowned);
+/// let size = value.size_of_val(&mut tracker);
+///
+/// assert!(size < value.size_of_val(&mut Tracker::new()));
+/// assert!(tracker.shared_bytes() > 0);
+/// ```
+#[derive(Debug, Default)]
+pub struct Tracker {
+    visited: BTreeSet<*const ()>,
+    visited_values: BTreeSet<(*const (), usize)>,
+    shared_policy: SharedPolicy,
+    shared_allocations: BTreeMap<*const (), usize>,
+    lock_policy: LockPolicy,
+    unmeasured: Vec<&'static str>,
+    usable_sizes: bool,
+    max_depth: Option<usize>,
+    max_values: Option<usize>,
+    /// The number of heap allocations and references being visited.
+    depth: usize,
+    /// The number of values visited so far.
+    values: usize,
+    truncated: Vec<&'static str>,
+    sample_size: Option<usize>,
+    /// The sum of the variances of the sampled estimates.
+    sampling_variance: f64,
+}
+
+impl Tracker {
+    /// Creates a new tracker, with the [`SharedPolicy::FirstOwner`] and
+    /// [`LockPolicy::Skip`] policies.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the accounting policy for shared allocations.
+    pub fn with_shared_policy(mut self, shared_policy: SharedPolicy) -> Self {
+        self.shared_policy = shared_policy;
+
+        self
+    }
+
+    /// Sets what to do when a value is behind a lock that is held.
+    pub fn with_lock_policy(mut self, lock_policy: LockPolicy) -> Self {
+        self.lock_policy = lock_policy;
+
+        self
+    }
+
+    /// Doesn't visit the values stored more than `depth` heap allocations
+    /// or references away from the measured value, e.g. with a depth of
+    /// 1, the elements of a `Vec<Vec<u8>>` are visited but not their
+    /// items. The heap allocations are still counted, not what they own.
+    ///
+    /// It bounds the recursion of deeply nested values, like long linked
+    /// lists, that could otherwise overflow the stack. The values that are
+    /// not visited are reported, see [`Tracker::truncated_values`].
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+
+        self
+    }
+
+    /// Stops visiting values once `count` values contained in the
+    /// measured values have been visited by this tracker, e.g. the
+    /// elements of collections, the fields of structs or the pointees of
+    /// references.
+    ///
+    /// It bounds the time spent measuring huge values. The values that
+    /// are not visited are reported, see [`Tracker::truncated_values`].
+    pub fn with_max_values(mut self, count: usize) -> Self {
+        self.max_values = Some(count);
+
+        self
+    }
+
+    /// Measures at most `sample_size` elements of each collection, evenly
+    /// spaced, and extrapolates what they own to all the elements, see
+    /// [`MemoryUsageTracker::sample_size`].
+    ///
+    /// It bounds the time spent measuring large collections of similar
+    /// elements, e.g. a `Vec<String>` with millions of strings. The
+    /// measured sizes are then estimates, see
+    /// [`Tracker::sampling_margin`]. The pointees of the shared pointers
+    /// and references of the sampled elements are measured once and not
+    /// extrapolated, so those only reachable from other elements are
+    /// missed.
+    ///
+    /// [`Tracker::histogram`], [`Tracker::report`] and [`Tracker::graph`]
+    /// measure every element, as they count the values one by one.
+    ///
+    /// ```rust
+    /// use loupe::{MemoryUsage, Tracker};
+    ///
+    /// let names = (0..10_000).map(|i| i.to_string()).collect::<Vec<_>>();
+    ///
+    /// let mut tracker = Tracker::new().with_sampling(100);
+    /// let estimate = names.size_of_val(&mut tracker) as f64;
+    /// let size = names.size_of_val(&mut Tracker::new()) as f64;
+    ///
+    /// assert!((estimate - size).abs() <= tracker.sampling_margin() as f64);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_size` is lower than 2, which is required to
+    /// estimate the variance of the elements.
+    pub fn with_sampling(mut self, sample_size: usize) -> Self {
+        assert!(sample_size >= 2, "the sample size must be at least 2");
+        self.sample_size = Some(sample_size);
+
+        self
+    }
+
+    /// Measures the heap blocks with the size the system allocator has
+    /// reserved for them, instead of the size that has been requested.
+    ///
+    /// The allocator rounds the requested sizes up, so the measured sizes
+    /// are larger, especially for small blocks. The difference is also
+    /// reported as unused capacity. It is only supported on Linux, Android,
+    /// FreeBSD, macOS and Windows; elsewhere, the sizes are unchanged. The
+    /// blocks whose start is unknown, like the allocations of `Rc` and
+    /// `Arc`, are still measured with their requested size.
+    ///
+    /// # Safety
+    ///
+    /// The measured values must be allocated by [`std::alloc::System`],
+    /// which is the default global allocator. Asking the system allocator
+    /// about a block allocated by another allocator is undefined behavior.
+    pub unsafe fn with_usable_sizes(mut self) -> Self {
+        self.usable_sizes = true;
+
+        self
+    }
+
+    /// Returns the size of `value` in bytes, like
+    /// [`MemoryUsage::size_of_val`].
+    ///
+    /// With the [`LockPolicy::Fail`] policy, an error is returned if a
+    /// value could not be measured. With limits, the size is a lower bound
+    /// if values have not been visited, see [`Tracker::is_truncated`].
+    pub fn measure<T>(&mut self, value: &T) -> Result<usize, MeasureError>
+    where
+        T: MemoryUsage + ?Sized,
+    {
+        self.check_measured(|tracker| value.size_of_val(tracker))
+    }
+
+    /// Runs `measure`, and returns an error if a value could not be
+    /// measured with the [`LockPolicy::Fail`] policy.
+    pub(crate) fn check_measured<R, F>(&mut self, measure: F) -> Result<R, MeasureError>
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let unmeasured = self.unmeasured.len();
+        let result = measure(self);
+
+        match self.unmeasured.get(unmeasured) {
+            Some(type_name) if self.lock_policy == LockPolicy::Fail => {
+                Err(MeasureError::Unmeasured { type_name })
+            }
+            _ => Ok(result),
+        }
+    }
+
+    /// Returns the names of the types whose value could not be measured,
+    /// in the order they have been visited.
+    pub fn unmeasured_values(&self) -> &[&'static str] {
+        &self.unmeasured
+    }
+
+    /// Returns the names of the types whose values have not been visited
+    /// because of the limits of the tracker, in the order they have been
+    /// visited. See [`Tracker::with_max_depth`] and
+    /// [`Tracker::with_max_values`].
+    ///
+    /// When a value of a collection is not visited, the next ones are not
+    /// either, only the first one is reported.
+    pub fn truncated_values(&self) -> &[&'static str] {
+        &self.truncated
+    }
+
+    /// Returns whether values have not been visited because of the limits
+    /// of the tracker, in which case the measured sizes are lower bounds.
+    pub fn is_truncated(&self) -> bool {
+        !self.truncated.is_empty()
+    }
+
+    /// Returns the margin of error of the sizes measured so far in bytes,
+    /// at a 95% confidence level, when collections have been sampled, see
+    /// [`Tracker::with_sampling`]. It is zero if nothing has been sampled.
+    ///
+    /// The exact size is within the measured size plus or minus the
+    /// margin 19 times out of 20, assuming the sampled elements are
+    /// representative of the collection. A collection ordered by size, or
+    /// whose sizes repeat with the stride, defeats the sampling.
+    pub fn sampling_margin(&self) -> usize {
+        (1.96 * self.sampling_variance.sqrt()).ceil() as usize
+    }
+
+    /// Returns the total size of the distinct shared allocations visited so
+    /// far, whoever has been charged for them.
+    pub fn shared_bytes(&self) -> usize {
+        self.shared_allocations.values().sum()
+    }
+}
+
+impl MemoryUsageTracker for Tracker {
+    fn track(&mut self, address: *const ()) -> bool {
+        self.visited.insert(address)
+    }
+
+    fn track_value(&mut self, address: *const (), size: usize) -> bool {
+        self.visited_values.insert((address, size))
+    }
+
+    fn shared_policy(&self) -> SharedPolicy {
+        self.shared_policy
+    }
+
+    fn shared_allocation(&mut self, address: *const (), bytes: usize) {
+        self.shared_allocations.insert(address, bytes);
+    }
+
+    fn size_of_shared_allocation(&self, address: *const ()) -> Option<usize> {
+        self.shared_allocations.get(&address).copied()
+    }
+
+    fn lock_policy(&self) -> LockPolicy {
+        self.lock_policy
+    }
+
+    fn record_unmeasured(&mut self, type_name: &'static str) {
+        self.unmeasured.push(type_name);
+    }
+
+    fn allocation_size(&self, address: *const (), layout: Layout) -> usize {
+        if !self.usable_sizes {
+            return layout.size();
+        }
+
+        // SAFETY: the block has been allocated by `System`, as promised by
+        // the caller of `with_usable_sizes`.
+        unsafe { usable_size(address as *const u8, layout) }.unwrap_or(layout.size())
+    }
+
+    fn heap_allocation(&mut self, _address: *const (), _type_name: &'static str, _bytes: usize) {
+        self.depth += 1;
+    }
+
+    fn exit_heap_allocation(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn borrowed_reference(&mut self, _address: *const (), _type_name: &'static str) {
+        self.depth += 1;
+    }
+
+    fn exit_borrowed_reference(&mut self, _bytes: usize) {
+        self.depth -= 1;
+    }
+
+    fn should_stop(&mut self) -> bool {
+        let too_deep = self
+            .max_depth
+            .is_some_and(|max_depth| self.depth > max_depth);
+        let too_many = self
+            .max_values
+            .is_some_and(|max_values| self.values >= max_values);
+
+        if too_deep || too_many {
+            return true;
+        }
+
+        self.values += 1;
+
+        false
+    }
+
+    fn record_truncated(&mut self, type_name: &'static str) {
+        self.truncated.push(type_name);
+    }
+
+    fn sample_size(&self) -> Option<usize> {
+        self.sample_size
+    }
+
+    fn sampled_elements(
+        &mut self,
+        _type_name: &'static str,
+        _sampled: usize,
+        _len: usize,
+        standard_error: f64,
+    ) {
+        // The estimates of different collections are independent.
+        self.sampling_variance += standard_error * standard_error;
+    }
+}
+
+/// An error returned by [`Tracker::measure`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeasureError {
+    /// A value could not be measured, see [`LockPolicy::Fail`].
+    Unmeasured {
+        /// The name of the type that contains the value.
+        type_name: &'static str,
+    },
+
+    /// The value is larger than the budget, see
+    /// [`Tracker::measure_within`].
+    BudgetExceeded {
+        /// The names of the fields from the root to the field being
+        /// visited when the budget was exceeded, joined by `.`. It is
+        /// empty for the root.
+        path: String,
+        /// The budget in bytes.
+        budget: usize,
+    },
+}
+
+impl fmt::Display for MeasureError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unmeasured { type_name } => {
+                write!(
+                    formatter,
+                    "a value of type `{}` could not be measured",
+                    type_name
+                )
+            }
+            Self::BudgetExceeded { path, budget } => {
+                write!(
+                    formatter,
+                    "the budget of {} bytes has been exceeded at {}",
+                    budget,
+                    if path.is_empty() { "<root>" } else { path }
+                )
+            }
+        }
+    }
+}
+
+impl Error for MeasureError {}
+
+/// A [`MemoryUsageTracker`] adapter that doesn't follow references.
+///
+/// Every address is considered as already tracked, so references, `Rc`
+/// and `Arc` only count their own size, not their pointee. Owned values,
+/// like the pointee of a `Box`, are still measured. Everything else is
+/// forwarded to the wrapped tracker.
+///
+/// It is used by the `#[loupe(shallow)]` attribute of the derive macro.
+pub struct ShallowTracker<'a> {
+    tracker: &'a mut dyn MemoryUsageTracker,
+}
+
+impl<'a> ShallowTracker<'a> {
+    /// Wraps `tracker`.
+    pub fn new(tracker: &'a mut dyn MemoryUsageTracker) -> Self {
+        Self { tracker }
+    }
+}
+
+impl MemoryUsageTracker for ShallowTracker<'_> {
+    fn track(&mut self, _address: *const ()) -> bool {
+        false
+    }
+
+    fn inner(&self) -> Option<&dyn MemoryUsageTracker> {
+        Some(&*self.tracker)
+    }
+
+    fn inner_mut(&mut self) -> Option<&mut dyn MemoryUsageTracker> {
+        Some(&mut *self.tracker)
+    }
+
+    fn track_value(&mut self, _address: *const (), _size: usize) -> bool {
+        false
+    }
+
+    fn size_of_shared_allocation(&self, _address: *const ()) -> Option<usize> {
+        None
+    }
+}
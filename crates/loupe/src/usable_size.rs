@@ -0,0 +1,43 @@
+//! Asks the system allocator for the real size of heap blocks.
+
+use std::alloc::Layout;
+
+/// Returns the size of the block allocated by the system allocator for
+/// `layout` at `address`, if the platform can tell it.
+///
+/// # Safety
+///
+/// `address` must be the start of a live block allocated for `layout` by
+/// [`std::alloc::System`].
+#[allow(unused_variables)]
+pub(crate) unsafe fn usable_size(address: *const u8, layout: Layout) -> Option<usize> {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "dragonfly"
+    ))]
+    return Some(libc::malloc_usable_size(address as *mut libc::c_void));
+
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    return Some(libc::malloc_size(address as *const libc::c_void));
+
+    #[cfg(target_os = "windows")]
+    {
+        use winapi::um::heapapi::{GetProcessHeap, HeapSize};
+
+        // Over-aligned blocks are allocated with a header, so `address` is
+        // not the start of the block that `HeapAlloc` returned.
+        if layout.align() > 2 * std::mem::size_of::<usize>() {
+            return None;
+        }
+
+        return match HeapSize(GetProcessHeap(), 0, address as *const _) {
+            usize::MAX => None,
+            size => Some(size),
+        };
+    }
+
+    #[allow(unreachable_code)]
+    None
+}